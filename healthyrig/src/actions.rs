@@ -0,0 +1,145 @@
+//! Remote actions the controller can take before cutting power
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Restart monitored service
+    Restart,
+    /// Stop monitored service
+    Stop,
+    /// Clean OS reboot
+    Reboot,
+    /// Clean OS shutdown
+    Poweroff,
+}
+
+impl Action {
+    pub fn parse(name: &str) -> Option<Action> {
+        match name {
+            "restart" => Some(Action::Restart),
+            "stop" => Some(Action::Stop),
+            "reboot" => Some(Action::Reboot),
+            "poweroff" => Some(Action::Poweroff),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Restart => "restart",
+            Action::Stop => "stop",
+            Action::Reboot => "reboot",
+            Action::Poweroff => "poweroff",
+        }
+    }
+
+    /// systemctl arguments.
+    /// Actions do not wait for the job, so a slow unit does not hold the server
+    /// and the response still gets out before reboot.
+    pub fn systemctl_args<'a>(&self, service: &'a str) -> Vec<&'a str> {
+        match *self {
+            Action::Restart => vec!["--no-block", "restart", service],
+            Action::Stop => vec!["--no-block", "stop", service],
+            Action::Reboot => vec!["--no-block", "reboot"],
+            Action::Poweroff => vec!["--no-block", "poweroff"],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ActionsCfg {
+    /// Shared secret expected in `Authorization: Bearer` header, actions are disabled if not set
+    pub token: Option<String>,
+    /// Actions that can be requested
+    pub allow: Vec<Action>,
+    /// Every action request is appended here
+    pub audit_log: String,
+}
+
+impl Default for ActionsCfg {
+    fn default() -> ActionsCfg {
+        ActionsCfg {
+            token: None,
+            allow: Vec::new(),
+            audit_log: String::from("/var/log/healthyrig-audit.log"),
+        }
+    }
+}
+
+impl ActionsCfg {
+    /// HTTP status code for denied request
+    pub fn authorize(&self, action: Action, authorization: Option<&str>) -> Result<(), u16> {
+        let token = match self.token {
+            Some(ref t) if !t.is_empty() => t,
+            _ => return Err(403),
+        };
+        let given = authorization
+            .and_then(|h| h.trim().strip_prefix("Bearer "))
+            .map(|t| t.trim())
+            .unwrap_or("");
+        if !constant_time_eq(given.as_bytes(), token.as_bytes()) {
+            return Err(401);
+        }
+        if !self.allow.contains(&action) {
+            return Err(403);
+        }
+        Ok(())
+    }
+
+    /// Append line to audit log
+    pub fn audit(&self, line: &str) {
+        println!("AUDIT {}", line);
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if let Some(dir) = Path::new(&self.audit_log).parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let res = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.audit_log)
+            .and_then(|mut f| writeln!(f, "{} {}", ts, line));
+        if let Err(e) = res {
+            println!("ERROR: Can not write audit log {}: {}", self.audit_log, e);
+        }
+    }
+}
+
+/// Compare secrets without leaking matched prefix length through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authorize_requests() {
+        let mut cfg = ActionsCfg {
+            allow: vec![Action::Restart, Action::Reboot],
+            ..ActionsCfg::default()
+        };
+        // no token configured
+        assert_eq!(cfg.authorize(Action::Restart, Some("Bearer ")), Err(403));
+
+        cfg.token = Some(String::from("s3cret"));
+        assert_eq!(cfg.authorize(Action::Restart, Some("Bearer s3cret")), Ok(()));
+        assert_eq!(cfg.authorize(Action::Reboot, Some("Bearer s3cret")), Ok(()));
+        assert_eq!(cfg.authorize(Action::Stop, Some("Bearer s3cret")), Err(403));
+        assert_eq!(cfg.authorize(Action::Restart, Some("Bearer s3cre")), Err(401));
+        assert_eq!(cfg.authorize(Action::Restart, Some("s3cret")), Err(401));
+        assert_eq!(cfg.authorize(Action::Restart, None), Err(401));
+    }
+}
@@ -0,0 +1,794 @@
+//! Health checks of the rig
+
+use actions::Action;
+use config::{Config, ServiceKind};
+use finding::{Code, Finding, Severity};
+use gpu::{self, Gpu};
+use logs::{self, LogState};
+use miner::{self, MinerCfg, MinerStats, ShareWindow};
+use os::{self, OsStats};
+use sys::Host;
+
+use libc;
+use regex::Regex;
+
+use std::fs::{read_dir, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub hostname: String,
+    /// Unix time of the check
+    pub timestamp: u64,
+    /// Seconds passed since the check when served from background sample
+    pub age: u64,
+    pub temp: Vec<i32>,
+    /// All required services are active
+    pub service: bool,
+    pub hw_errors: bool,
+    pub services: Vec<ServiceStatus>,
+    pub gpus: Vec<Gpu>,
+    pub findings: Vec<Finding>,
+    /// Miner API stats, if miner is configured and answers
+    pub miner: Option<MinerStats>,
+    /// Host OS health
+    #[serde(default)]
+    pub os: OsStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub kind: ServiceKind,
+    pub required: bool,
+    pub active: bool,
+}
+
+/// Runs checks against host with given settings
+pub struct Checker {
+    pub cfg: Config,
+    host: Host,
+    shares: Mutex<ShareWindow>,
+    logs: Mutex<LogState>,
+    kernel_logs: Mutex<LogState>,
+}
+
+impl Checker {
+    pub fn new(cfg: Config, host: Host) -> Checker {
+        Checker {
+            cfg,
+            host,
+            shares: Mutex::new(ShareWindow::default()),
+            logs: Mutex::new(LogState::default()),
+            kernel_logs: Mutex::new(LogState::default()),
+        }
+    }
+
+    pub fn check_all(&self) -> CheckResult {
+        let (gpus, nvidia_error) = self.check_gpus();
+        let mut findings = self.check_hw_errors(&gpus);
+        findings.extend(nvidia_error);
+        let (miner, miner_findings) = self.check_miner();
+        findings.extend(miner_findings);
+        let os = os::check(&self.host);
+        findings.extend(self.check_os(&os));
+        let services = self.check_services();
+
+        CheckResult {
+            hostname: self.check_hostname(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            age: 0,
+            temp: gpus.iter().filter_map(|g| g.temp).collect(),
+            service: services.iter().all(|s| s.active || !s.required),
+            hw_errors: findings.iter().any(|f| f.is_critical()),
+            services,
+            gpus,
+            findings,
+            miner,
+            os,
+        }
+    }
+
+    pub fn check_gpus(&self) -> (Vec<Gpu>, Option<Finding>) {
+        gpu::check_gpus(&self.host, &self.cfg.hwmon)
+    }
+
+    pub fn check_hw_errors(&self, gpus: &[Gpu]) -> Vec<Finding> {
+        let cfg = &self.cfg;
+        let mut res = Vec::new();
+
+        let readings = gpu::readings(gpus);
+        if cfg.gpus > 0 && readings != cfg.gpus {
+            res.push(Finding::new(
+                Code::GpuCount,
+                Severity::Critical,
+                format!("Expected {} GPUs but got {} readings", cfg.gpus, readings),
+            ));
+        }
+
+        for g in gpus {
+            match (g.temp, cfg.thresholds.gpu_temp_max) {
+                (None, _) => res.push(
+                    Finding::new(
+                        Code::TempRead,
+                        Severity::Warning,
+                        String::from("Can not read GPU temperature"),
+                    ).gpu(g.index),
+                ),
+                (Some(t), Some(max)) if t > max => res.push(
+                    Finding::new(
+                        Code::GpuTemp,
+                        Severity::Critical,
+                        format!("GPU temperature {}C is above {}C", t, max),
+                    ).gpu(g.index),
+                ),
+                _ => {}
+            }
+
+            let sensors = [
+                ("junction", g.temp_junction, cfg.thresholds.gpu_junction_temp_max),
+                ("memory", g.temp_mem, cfg.thresholds.gpu_mem_temp_max),
+            ];
+            for &(sensor, temp, max) in &sensors {
+                if let (Some(t), Some(max)) = (temp, max) {
+                    if t > max {
+                        res.push(
+                            Finding::new(
+                                Code::GpuTemp,
+                                Severity::Critical,
+                                format!("GPU {} temperature {}C is above {}C", sensor, t, max),
+                            ).gpu(g.index),
+                        );
+                    }
+                }
+            }
+
+            if let (Some(rpm), Some(min)) = (g.fan_rpm, cfg.thresholds.gpu_fan_rpm_min) {
+                if rpm < min {
+                    res.push(
+                        Finding::new(
+                            Code::GpuFan,
+                            Severity::Warning,
+                            format!("GPU fan speed {} RPM is below {} RPM", rpm, min),
+                        ).gpu(g.index),
+                    );
+                }
+            }
+        }
+
+        res.extend(self.check_logs());
+        res.extend(self.check_kernel_logs(gpus));
+        res
+    }
+
+    /// Compare OS readings with thresholds
+    pub fn check_os(&self, os: &OsStats) -> Vec<Finding> {
+        let t = &self.cfg.thresholds;
+        let mut res = Vec::new();
+        if let (Some(temp), Some(max)) = (os.cpu_temp, t.cpu_temp_max) {
+            if temp > max {
+                res.push(Finding::new(
+                    Code::CpuTemp,
+                    Severity::Critical,
+                    format!("CPU temperature {}C is above {}C", temp, max),
+                ));
+            }
+        }
+        if let (Some(free), Some(min)) = (os.mem_available_percent(), t.mem_available_min) {
+            if free < min {
+                res.push(Finding::new(
+                    Code::LowMemory,
+                    Severity::Warning,
+                    format!("Available memory {:.1}% is below {}%", free, min),
+                ));
+            }
+        }
+        if let (Some(free), Some(min)) = (os.disk_free_percent(), t.disk_free_min) {
+            if free < min {
+                res.push(Finding::new(
+                    Code::DiskSpace,
+                    Severity::Warning,
+                    format!("Root filesystem free space {:.1}% is below {}%", free, min),
+                ));
+            }
+        }
+        res
+    }
+
+    /// Miner API stats and problems talking to it
+    pub fn check_miner(&self) -> (Option<MinerStats>, Vec<Finding>) {
+        let cfg = match self.cfg.miner {
+            Some(ref cfg) => cfg,
+            None => return (None, Vec::new()),
+        };
+        match miner::check(cfg) {
+            Ok(stats) => {
+                let findings = self.check_hashrate(cfg, &stats);
+                (Some(stats), findings)
+            }
+            Err(e) => {
+                println!("ERROR: {}", e);
+                (None, vec![Finding::new(Code::MinerApi, Severity::Warning, e)])
+            }
+        }
+    }
+
+    /// Compare miner stats with expected hashrate and rejected shares ratio
+    pub fn check_hashrate(&self, cfg: &MinerCfg, stats: &MinerStats) -> Vec<Finding> {
+        let mut res = Vec::new();
+        let min_ratio = (100.0 - cfg.hashrate_tolerance) / 100.0;
+
+        if stats.uptime >= cfg.warmup {
+            if let Some(expected) = cfg.expected_hashrate {
+                if stats.hashrate < expected * min_ratio {
+                    res.push(Finding::new(
+                        Code::LowHashrate,
+                        Severity::Critical,
+                        format!(
+                            "Hashrate {:.0} H/s is below expected {:.0} H/s",
+                            stats.hashrate, expected
+                        ),
+                    ));
+                }
+            }
+            if let Some(expected) = cfg.expected_gpu_hashrate {
+                for (i, &h) in stats.gpus.iter().enumerate() {
+                    // 0 H/s is GPU disabled in miner
+                    if h > 0.0 && h < expected * min_ratio {
+                        res.push(
+                            Finding::new(
+                                Code::LowHashrate,
+                                cfg.gpu_hashrate_severity,
+                                format!(
+                                    "Miner GPU {} hashrate {:.0} H/s is below expected {:.0} H/s",
+                                    i, h, expected
+                                ),
+                            ).gpu(i),
+                        );
+                    }
+                }
+            }
+        }
+
+        let ratio = self.shares.lock().unwrap().push(
+            Instant::now(),
+            Duration::from_secs(cfg.share_window),
+            stats.accepted,
+            stats.rejected,
+        );
+        if let (Some(ratio), Some(max)) = (ratio, cfg.reject_ratio_max) {
+            if ratio > max {
+                res.push(Finding::new(
+                    Code::RejectRatio,
+                    Severity::Warning,
+                    format!("Rejected shares {:.1}% is above {:.1}%", ratio, max),
+                ));
+            }
+        }
+        res
+    }
+
+    /// Findings from miner log entries seen within `active_for` period.
+    /// Only entries added since the previous scan are read.
+    pub fn check_logs(&self) -> Vec<Finding> {
+        let mut state = self.logs.lock().unwrap();
+        let logs = match self.cfg.logs.file {
+            Some(ref path) => self.read_log_file(path, &mut state),
+            None => {
+                let units = self.cfg.units();
+                let mut source = Vec::new();
+                for unit in &units {
+                    source.extend_from_slice(&["-u", unit.as_str()]);
+                }
+                if source.is_empty() {
+                    String::new()
+                } else {
+                    self.read_journal(&source, self.cfg.logs.lines, &mut state)
+                }
+            }
+        };
+
+        let found = logs::scan(Code::LogPattern, &self.cfg.logs.rules, &logs);
+        state.update(
+            Instant::now(),
+            Duration::from_secs(self.cfg.logs.active_for),
+            found,
+        )
+    }
+
+    /// Kernel log errors seen within `kernel.active_for`.
+    /// Findings are bound to GPU by PCI bus ID in message.
+    pub fn check_kernel_logs(&self, gpus: &[Gpu]) -> Vec<Finding> {
+        let cfg = &self.cfg.kernel;
+        if !cfg.enabled {
+            return Vec::new();
+        }
+        let mut state = self.kernel_logs.lock().unwrap();
+        let logs = if cfg.kmsg {
+            self.read_kmsg(&mut state)
+        } else {
+            self.read_journal(&["-k"], cfg.lines, &mut state)
+        };
+
+        let mut found = logs::scan(Code::KernelLog, &cfg.rules, &logs);
+        for f in &mut found {
+            if f.gpu.is_none() {
+                f.gpu = f.line.as_ref().and_then(|l| gpu_by_bus_id(gpus, l));
+            }
+        }
+        state.update(Instant::now(), Duration::from_secs(cfg.active_for), found)
+    }
+
+    /// Kernel messages since the previous scan
+    fn read_kmsg(&self, state: &mut LogState) -> String {
+        let res = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(self.host.path("/dev/kmsg"))
+            .and_then(|mut f| logs::read_kmsg(&mut f, state.kmsg_seq, self.cfg.kernel.lines));
+        match res {
+            Ok((logs, seq)) => {
+                state.kmsg_seq = seq;
+                logs
+            }
+            Err(e) => {
+                println!("ERROR: Can not read /dev/kmsg: {}", e);
+                String::new()
+            }
+        }
+    }
+
+    /// Journal entries after cursor or last `lines` entries on first scan
+    fn read_journal(&self, source: &[&str], lines: usize, state: &mut LogState) -> String {
+        // journalctl -b 0 -o cat --show-cursor -u miner -u watchdog -n 100
+        let lines = lines.to_string();
+        let mut args = vec!["-b", "0", "-o", "cat", "--show-cursor"];
+        args.extend_from_slice(source);
+        match state.cursor {
+            Some(ref cursor) => args.extend_from_slice(&["--after-cursor", cursor]),
+            None => args.extend_from_slice(&["-n", &lines]),
+        }
+        let out = self.host
+            .run("journalctl", &args)
+            .map(|out| out.stdout)
+            .unwrap_or_default();
+
+        let (logs, cursor) = logs::split_cursor(&out);
+        if let Some(cursor) = cursor {
+            state.cursor = Some(String::from(cursor));
+        }
+        String::from(logs)
+    }
+
+    /// Log file lines appended since the previous scan
+    fn read_log_file(&self, path: &str, state: &mut LogState) -> String {
+        let res = self.host
+            .open(path)
+            .and_then(|mut f| logs::tail(&mut f, state.file, self.cfg.logs.lines));
+        match res {
+            Ok((logs, pos)) => {
+                state.file = Some(pos);
+                logs
+            }
+            Err(e) => {
+                println!("ERROR: Can not read log file {}: {}", path, e);
+                String::new()
+            }
+        }
+    }
+
+    pub fn check_services(&self) -> Vec<ServiceStatus> {
+        self.cfg
+            .services()
+            .into_iter()
+            .map(|s| ServiceStatus {
+                active: match s.kind {
+                    ServiceKind::Systemd => self.check_service(&s.name),
+                    ServiceKind::Process => self.check_process(&s.name),
+                },
+                name: s.name,
+                kind: s.kind,
+                required: s.required,
+            })
+            .collect()
+    }
+
+    /// Whether process with given name is running.
+    /// Name is matched against `comm`, which is cut to 15 chars, and program name from `cmdline`.
+    pub fn check_process(&self, name: &str) -> bool {
+        let proc_dir = match read_dir(self.host.path("/proc")) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("ERROR: Can not read /proc: {}", e);
+                return false;
+            }
+        };
+        proc_dir
+            .filter_map(|r| r.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|pid| pid.chars().all(|c| c.is_ascii_digit()))
+            .any(|pid| {
+                let comm = self.host.read_string(format!("/proc/{}/comm", pid));
+                let cmdline = self.host.read_string(format!("/proc/{}/cmdline", pid));
+                comm.map(|c| c.trim() == name).unwrap_or(false)
+                    || cmdline
+                        .as_ref()
+                        .and_then(|c| c.split('\0').next())
+                        .and_then(|prog| prog.rsplit('/').next())
+                        .map(|prog| prog == name)
+                        .unwrap_or(false)
+            })
+    }
+
+    /// Run action with systemctl, `unit` is the service to restart or stop
+    pub fn run_action(&self, action: Action, unit: &str) -> Result<(), String> {
+        match self.host.run("systemctl", &action.systemctl_args(unit)) {
+            Ok(ref out) if out.success => Ok(()),
+            Ok(out) => Err(format!("systemctl {} failed: {}", action.name(), out.stderr.trim())),
+            Err(e) => Err(format!("Can not call systemctl: {}", e)),
+        }
+    }
+
+    pub fn check_service(&self, name: &str) -> bool {
+        match self.host.run("systemctl", &["is-active", name]) {
+            Ok(out) => {
+                #[cfg(debug_assertions)]
+                {
+                    println!("systemctl is-active {} >> {}", name, out.stdout.trim());
+                }
+                out.stdout.trim() == "active"
+            }
+            Err(e) => {
+                println!("ERROR: Can not call systemctl: {}", e);
+                false
+            }
+        }
+    }
+
+    pub fn check_hostname(&self) -> String {
+        match self.host.run("hostname", &[]) {
+            Ok(out) => String::from(out.stdout.trim()),
+            Err(_) => String::from("undefined"),
+        }
+    }
+}
+
+lazy_static! {
+    /// PCI address in kernel message, domain may be 4 or 8 hex digits
+    static ref BUS_ID_RE: Regex =
+        Regex::new(r"(?i)\b[0-9a-f]{4,8}:[0-9a-f]{2}:[0-9a-f]{2}\b").unwrap();
+}
+
+/// Index of GPU with PCI bus ID found in kernel message
+fn gpu_by_bus_id(gpus: &[Gpu], line: &str) -> Option<usize> {
+    // amdgpu 0000:03:00.0, NVRM: Xid (PCI:0000:01:00), nvidia-smi 00000000:01:00.0
+    let short = |id: &str| {
+        let id = id.split('.').next().unwrap_or("");
+        let parts: Vec<&str> = id.rsplitn(3, ':').collect();
+        match parts.as_slice() {
+            [dev, bus, _domain] => Some(format!("{}:{}", bus, dev).to_lowercase()),
+            _ => None,
+        }
+    };
+    BUS_ID_RE.find_iter(line).filter_map(|m| short(m.as_str())).find_map(|id| {
+        gpus.iter()
+            .find(|g| g.bus_id.as_deref().and_then(short).as_ref() == Some(&id))
+            .map(|g| g.index)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{ServiceCfg, Thresholds};
+    use gpu::Vendor;
+    use miner::MinerKind;
+    use nvidia;
+    use sys::fake::{FakeRoot, FakeRunner};
+
+    static JOURNALCTL: &str = "journalctl -b 0 -o cat --show-cursor -u miner -n 100";
+
+    /// Two RX 580 cards, the second one is overheating
+    fn amd_rig() -> FakeRoot {
+        let root = FakeRoot::new();
+        let cards = [
+            ("hwmon1", "0000:03:00.0", "84000\n"),
+            ("hwmon0", "0000:01:00.0", "62000\n"),
+        ];
+        for &(hwmon, bus_id, temp) in &cards {
+            let dev = format!("/sys/devices/pci0000:00/{}", bus_id);
+            let dir = format!("/sys/class/hwmon/{}", hwmon);
+            root.write(&format!("{}/gpu_busy_percent", dev), "100\n")
+                .symlink(&format!("{}/device", dir), &dev)
+                .write(&format!("{}/name", dir), "amdgpu\n")
+                .write(&format!("{}/temp1_input", dir), temp)
+                .write(&format!("{}/fan1_input", dir), "2100\n")
+                .write(&format!("{}/pwm1", dir), "153\n")
+                .write(&format!("{}/pwm1_max", dir), "255\n")
+                .write(&format!("{}/power1_average", dir), "121000000\n");
+        }
+        root.write("/sys/class/hwmon/hwmon2/name", "k10temp\n")
+            .write("/sys/class/hwmon/hwmon2/temp1_input", "45000\n");
+        root
+    }
+
+    fn runner() -> FakeRunner {
+        FakeRunner::default()
+            .with("hostname", "rig01\n")
+            .with("systemctl is-active miner", "active\n")
+            .with(
+                &format!("nvidia-smi {} {}", nvidia::QUERY, nvidia::FORMAT),
+                "0, 00000000:05:00.0, 61, 75, 112.34, 100, 1835, 4006, 0x0000000000000000\n",
+            )
+    }
+
+    fn checker(root: &FakeRoot, runner: FakeRunner) -> Checker {
+        let cfg = Config {
+            gpus: 3,
+            thresholds: Thresholds {
+                gpu_temp_max: Some(80),
+                ..Thresholds::default()
+            },
+            ..Config::default()
+        };
+        Checker::new(cfg, root.host(runner))
+    }
+
+    #[test]
+    fn check_all_mixed_rig() {
+        let root = amd_rig();
+        let r = checker(&root, runner()).check_all();
+
+        assert_eq!(r.hostname, "rig01");
+        assert!(r.service);
+        assert_eq!(r.temp, vec![62, 84, 61]);
+
+        let vendors: Vec<Vendor> = r.gpus.iter().map(|g| g.vendor).collect();
+        assert_eq!(vendors, vec![Vendor::Amd, Vendor::Amd, Vendor::Nvidia]);
+        let g = &r.gpus[0];
+        assert_eq!(g.index, 0);
+        assert_eq!(g.bus_id, Some(String::from("0000:01:00.0")));
+        assert_eq!(g.fan_rpm, Some(2100));
+        assert_eq!(g.fan, Some(60));
+        assert_eq!(g.power, Some(121.0));
+        assert_eq!(g.load, Some(100));
+        assert_eq!(r.gpus[2].index, 2);
+
+        assert!(r.hw_errors);
+        assert_eq!(r.findings.len(), 1);
+        assert_eq!(r.findings[0].code, Code::GpuTemp);
+        assert_eq!(r.findings[0].gpu, Some(1));
+    }
+
+    #[test]
+    fn check_all_failing_miner() {
+        let root = amd_rig();
+        root.write("/sys/class/hwmon/hwmon0/temp1_input", "");
+        let runner = runner()
+            .with("systemctl is-active miner", "failed\n")
+            .with(
+                JOURNALCTL,
+                "ETH: 12/01/18-10:00:00 - New job from eth.pool.com:4444\n\
+                 WATCHDOG: GPU error, you need to restart miner :(\n\
+                 -- cursor: s=6f1c3a;i=1a2b\n",
+            )
+            .with(
+                "journalctl -b 0 -o cat --show-cursor -u miner --after-cursor s=6f1c3a;i=1a2b",
+                "",
+            );
+        let checker = checker(&root, runner);
+        // error is still active on the next scan without new entries
+        checker.check_all();
+        let r = checker.check_all();
+
+        assert!(!r.service);
+        assert!(r.hw_errors);
+        let codes: Vec<Code> = r.findings.iter().map(|f| f.code).collect();
+        assert_eq!(
+            codes,
+            vec![Code::GpuCount, Code::TempRead, Code::GpuTemp, Code::LogPattern]
+        );
+        assert_eq!(r.findings[1].gpu, Some(0));
+        assert_eq!(r.findings[3].rule, Some(String::from("gpu_watchdog")));
+    }
+
+    #[test]
+    fn check_all_without_tools() {
+        let root = FakeRoot::new();
+        let r = checker(&root, FakeRunner::default()).check_all();
+
+        assert_eq!(r.hostname, "undefined");
+        assert!(!r.service);
+        assert!(r.gpus.is_empty());
+        assert_eq!(r.findings.len(), 1);
+        assert_eq!(r.findings[0].code, Code::GpuCount);
+    }
+
+    #[test]
+    fn check_all_with_lost_nvidia_card() {
+        let root = amd_rig();
+        let runner = runner().failing(
+            &format!("nvidia-smi {} {}", nvidia::QUERY, nvidia::FORMAT),
+            "0, 00000000:05:00.0, 61, 75, 112.34, 100, 1835, 4006, 0x0000000000000000\n\
+             Unable to determine the device handle for GPU 0000:06:00.0: Unknown Error\n",
+        );
+        let r = checker(&root, runner).check_all();
+
+        assert_eq!(r.temp, vec![62, 84, 61]);
+        let codes: Vec<Code> = r.findings.iter().map(|f| f.code).collect();
+        assert_eq!(codes, vec![Code::GpuTemp, Code::NvidiaSmi]);
+        assert_eq!(r.findings[1].severity, Severity::Warning);
+        assert_eq!(
+            r.findings[1].message,
+            "nvidia-smi failed: \
+             Unable to determine the device handle for GPU 0000:06:00.0: Unknown Error"
+        );
+    }
+
+    #[test]
+    fn check_kernel_logs_of_gpus() {
+        let root = amd_rig();
+        let kernel = "\
+amdgpu 0000:03:00.0: [drm:amdgpu_job_timedout] *ERROR* ring gfx timeout, signaled seq=1
+NVRM: Xid (PCI:0000:05:00): 79, pid=1234, GPU has fallen off the bus.
+pcieport 0000:00:1c.4: AER: Corrected error received: 0000:01:00.0
+Out of memory: Killed process 1234 (ethdcrminer64)
+-- cursor: s=k1
+";
+        let runner = runner().with(
+            "journalctl -b 0 -o cat --show-cursor -k -n 1000",
+            kernel,
+        );
+        let checker = checker(&root, runner);
+        let (gpus, _) = checker.check_gpus();
+
+        let found: Vec<(String, Severity, Option<usize>)> = checker
+            .check_kernel_logs(&gpus)
+            .into_iter()
+            .inspect(|f| assert_eq!(f.code, Code::KernelLog))
+            .map(|f| (f.rule.unwrap(), f.severity, f.gpu))
+            .collect();
+        let expected = [
+            ("amdgpu_ring_timeout", Severity::Critical, Some(1)),
+            ("nvrm_fallen_off_bus", Severity::Critical, Some(2)),
+            ("nvrm_xid", Severity::Warning, Some(2)),
+            ("pcie_corrected", Severity::Warning, Some(0)),
+            ("oom_kill", Severity::Warning, None),
+        ];
+        let expected: Vec<(String, Severity, Option<usize>)> = expected
+            .iter()
+            .map(|&(rule, severity, gpu)| (String::from(rule), severity, gpu))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn check_os_thresholds() {
+        let root = FakeRoot::new();
+        let mut checker = checker(&root, runner());
+        checker.cfg.thresholds = Thresholds {
+            cpu_temp_max: Some(90),
+            mem_available_min: Some(10.0),
+            disk_free_min: Some(5.0),
+            ..Thresholds::default()
+        };
+        let os = OsStats {
+            cpu_temp: Some(95),
+            mem_total: Some(8000),
+            mem_available: Some(400),
+            disk_total: Some(1000),
+            disk_free: Some(100),
+            ..OsStats::default()
+        };
+
+        let codes: Vec<Code> = checker.check_os(&os).iter().map(|f| f.code).collect();
+        assert_eq!(codes, vec![Code::CpuTemp, Code::LowMemory]);
+        assert!(checker.check_os(&OsStats::default()).is_empty());
+    }
+
+    #[test]
+    fn check_hashrate_degraded_card() {
+        let root = FakeRoot::new();
+        let checker = checker(&root, FakeRunner::default());
+        let cfg = MinerCfg {
+            expected_gpu_hashrate: Some(30_000_000.0),
+            reject_ratio_max: Some(5.0),
+            ..MinerCfg::default()
+        };
+        let mut stats = MinerStats {
+            kind: MinerKind::Claymore,
+            version: String::from("11.9 - ETH"),
+            uptime: 3600,
+            hashrate: 75_000_000.0,
+            // the last GPU is disabled in miner
+            gpus: vec![30_100_000.0, 14_900_000.0, 30_000_000.0, 0.0],
+            accepted: 1000,
+            rejected: 10,
+        };
+
+        let findings = checker.check_hashrate(&cfg, &stats);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].code, Code::LowHashrate);
+        assert_eq!(findings[0].severity, Severity::Warning);
+        assert_eq!(findings[0].gpu, Some(1));
+
+        stats.accepted += 40;
+        stats.rejected += 10;
+        let codes: Vec<Code> = checker
+            .check_hashrate(&cfg, &stats)
+            .iter()
+            .map(|f| f.code)
+            .collect();
+        assert_eq!(codes, vec![Code::LowHashrate, Code::RejectRatio]);
+
+        stats.uptime = 60;
+        stats.accepted += 200;
+        assert!(checker.check_hashrate(&cfg, &stats).is_empty());
+    }
+
+    #[test]
+    fn check_services_and_processes() {
+        let root = FakeRoot::new();
+        root.write("/proc/1/comm", "systemd\n")
+            .write("/proc/1/cmdline", "/sbin/init\0splash\0")
+            .write("/proc/812/comm", "ethdcrminer64\n")
+            .write("/proc/812/cmdline", "/opt/claymore/ethdcrminer64\0-epool\0eth.pool.com\0")
+            .write("/proc/813/comm", "xmrig-nvidia\n")
+            .write("/proc/813/cmdline", "")
+            .write("/proc/self/comm", "healthyrig\n");
+        let runner = runner().with("systemctl is-active watchdog", "inactive\n");
+        let service = |name: &str, kind, required| ServiceCfg {
+            name: String::from(name),
+            kind,
+            required,
+        };
+        let mut checker = checker(&root, runner);
+        checker.cfg.services = vec![
+            service("miner", ServiceKind::Systemd, true),
+            service("watchdog", ServiceKind::Systemd, false),
+            service("ethdcrminer64", ServiceKind::Process, true),
+            service("xmrig-nvidia", ServiceKind::Process, true),
+        ];
+
+        let active: Vec<bool> = checker.check_services().iter().map(|s| s.active).collect();
+        assert_eq!(active, vec![true, false, true, true]);
+        assert!(checker.check_all().service);
+
+        checker.cfg.services.push(service("healthyrig", ServiceKind::Process, true));
+        assert!(!checker.check_all().service);
+    }
+
+    #[test]
+    fn scan_and_restart_all_units() {
+        let root = FakeRoot::new();
+        let runner = runner()
+            .with(
+                "journalctl -b 0 -o cat --show-cursor -u miner -u watchdog -n 100",
+                "watchdog: GPU #1 hangs in OpenCL call, exit\n-- cursor: s=w1\n",
+            )
+            .with("systemctl --no-block restart watchdog", "");
+        let service = |name: &str, kind| ServiceCfg {
+            name: String::from(name),
+            kind,
+            required: true,
+        };
+        let mut checker = checker(&root, runner);
+        checker.cfg.services = vec![
+            service("miner", ServiceKind::Systemd),
+            service("watchdog", ServiceKind::Systemd),
+            service("ethdcrminer64", ServiceKind::Process),
+        ];
+
+        let found = checker.check_logs();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].rule, Some(String::from("opencl_hang")));
+        assert_eq!(found[0].gpu, Some(1));
+        assert_eq!(checker.run_action(Action::Restart, "watchdog"), Ok(()));
+        assert!(checker.run_action(Action::Restart, "miner").is_err());
+    }
+}
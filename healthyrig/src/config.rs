@@ -0,0 +1,348 @@
+use actions::ActionsCfg;
+use discovery::BeaconCfg;
+use logs::{self, LogRule};
+use miner::MinerCfg;
+use push::PushCfg;
+use toml;
+
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+
+/// Healthyrig settings.
+/// Every field is optional in TOML file, command line options override file values.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// systemd service name to monitor and read logs of
+    pub service: String,
+    /// Services and processes to monitor, only `service` is monitored if empty
+    pub services: Vec<ServiceCfg>,
+    /// Expected GPUs count, 0 disables check
+    pub gpus: usize,
+    /// Address to bind server to
+    pub bind: String,
+    /// Server port, server will not start if not set
+    pub port: Option<usize>,
+    /// Shared key, requests must be signed with it and responses are signed when set
+    pub key: Option<String>,
+    /// Serve HTTPS instead of plain HTTP when set
+    pub tls: Option<TlsCfg>,
+    /// Seconds between background checks in server mode
+    pub interval: u64,
+    /// Host filesystem root, other paths are resolved under it
+    pub root: String,
+    /// Directory with hwmon devices
+    pub hwmon: String,
+    pub logs: LogsCfg,
+    pub kernel: KernelCfg,
+    pub history: HistoryCfg,
+    pub actions: ActionsCfg,
+    /// Controller to send heartbeats to, not sent if not set
+    pub push: Option<PushCfg>,
+    /// LAN discovery beacon, not sent if not set
+    pub beacon: Option<BeaconCfg>,
+    /// Miner stats API, not queried if not set
+    pub miner: Option<MinerCfg>,
+    pub thresholds: Thresholds,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceKind {
+    /// systemd unit checked with `systemctl is-active`
+    Systemd,
+    /// Process name found in `/proc`
+    Process,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServiceCfg {
+    pub name: String,
+    pub kind: ServiceKind,
+    /// Rig is healthy only when all required services are active
+    pub required: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TlsCfg {
+    /// PEM file with server certificate chain
+    pub cert: String,
+    /// PEM file with certificate private key
+    pub key: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogsCfg {
+    /// How many last service log lines to scan on start
+    pub lines: usize,
+    /// Seconds matched log error stays reported
+    pub active_for: u64,
+    /// Miner log file to tail instead of service journal
+    pub file: Option<String>,
+    /// Patterns that signal miner failures
+    pub rules: Vec<LogRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct KernelCfg {
+    /// Scan kernel log
+    pub enabled: bool,
+    /// Read `/dev/kmsg` instead of `journalctl -k`, for hosts without persistent journal
+    pub kmsg: bool,
+    /// How many last kernel messages to scan on start
+    pub lines: usize,
+    /// Seconds matched kernel message stays reported
+    pub active_for: u64,
+    /// Patterns of GPU driver, PCIe and memory failures
+    pub rules: Vec<LogRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct HistoryCfg {
+    /// How many last check results to keep
+    pub size: usize,
+    /// File to save history to on shutdown and load from on start
+    pub file: Option<String>,
+    /// Seconds between history saves, 0 saves on shutdown only
+    pub save_interval: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Thresholds {
+    /// GPU temperature that should be reported as hardware error
+    pub gpu_temp_max: Option<i32>,
+    /// GPU junction (hotspot) temperature that should be reported as hardware error
+    pub gpu_junction_temp_max: Option<i32>,
+    /// GPU memory temperature that should be reported as hardware error
+    pub gpu_mem_temp_max: Option<i32>,
+    /// Report GPU fan as failed when it runs slower
+    pub gpu_fan_rpm_min: Option<u32>,
+    /// CPU temperature that should be reported as hardware error
+    pub cpu_temp_max: Option<i32>,
+    /// Report available memory percent below
+    pub mem_available_min: Option<f64>,
+    /// Report root filesystem free space percent below
+    pub disk_free_min: Option<f64>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            service: String::from("miner"),
+            services: Vec::new(),
+            gpus: 0,
+            bind: String::from("0.0.0.0"),
+            port: None,
+            key: None,
+            tls: None,
+            interval: 10,
+            root: String::from("/"),
+            hwmon: String::from("/sys/class/hwmon"),
+            logs: LogsCfg::default(),
+            kernel: KernelCfg::default(),
+            history: HistoryCfg::default(),
+            actions: ActionsCfg::default(),
+            push: None,
+            beacon: None,
+            miner: None,
+            thresholds: Thresholds::default(),
+        }
+    }
+}
+
+impl ServiceKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ServiceKind::Systemd => "systemd",
+            ServiceKind::Process => "process",
+        }
+    }
+}
+
+impl Default for ServiceCfg {
+    fn default() -> ServiceCfg {
+        ServiceCfg {
+            name: String::new(),
+            kind: ServiceKind::Systemd,
+            required: true,
+        }
+    }
+}
+
+impl Default for LogsCfg {
+    fn default() -> LogsCfg {
+        LogsCfg {
+            lines: 100,
+            active_for: 600,
+            file: None,
+            rules: logs::default_rules(),
+        }
+    }
+}
+
+impl Default for KernelCfg {
+    fn default() -> KernelCfg {
+        KernelCfg {
+            enabled: true,
+            kmsg: false,
+            lines: 1000,
+            active_for: 600,
+            rules: logs::default_kernel_rules(),
+        }
+    }
+}
+
+impl Default for HistoryCfg {
+    fn default() -> HistoryCfg {
+        HistoryCfg {
+            size: 360,
+            file: None,
+            save_interval: 300,
+        }
+    }
+}
+
+impl Config {
+    /// Monitored services, `service` alone if list is not configured
+    pub fn services(&self) -> Vec<ServiceCfg> {
+        if !self.services.is_empty() {
+            return self.services.clone();
+        }
+        vec![ServiceCfg {
+            name: self.service.clone(),
+            ..ServiceCfg::default()
+        }]
+    }
+
+    /// systemd units among monitored services, their journal is scanned for errors
+    pub fn units(&self) -> Vec<String> {
+        self.services()
+            .into_iter()
+            .filter(|s| s.kind == ServiceKind::Systemd)
+            .map(|s| s.name)
+            .collect()
+    }
+
+    /// Unit to restart or stop: the named one if it is monitored,
+    /// the only monitored unit if name is not given
+    pub fn action_unit(&self, name: Option<&str>) -> Option<String> {
+        let mut units = self.units();
+        match name {
+            Some(name) => units.into_iter().find(|u| u == name),
+            None if units.len() == 1 => units.pop(),
+            None => None,
+        }
+    }
+
+    /// Action token would cross the network in cleartext without TLS or signed requests,
+    /// so actions are turned off then unless server listens on loopback only.
+    /// True if they were enabled.
+    pub fn disable_insecure_actions(&mut self) -> bool {
+        let loopback = self.bind.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false);
+        if self.actions.token.is_none() || self.tls.is_some() || self.key.is_some() || loopback {
+            return false;
+        }
+        self.actions.token = None;
+        true
+    }
+
+    pub fn load(path: &str) -> Result<Config, String> {
+        let mut s = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut s))
+            .map_err(|e| format!("Can not read config {}: {}", path, e))?;
+        toml::from_str::<Config>(&s).map_err(|e| format!("Can not parse config {}: {}", path, e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_config_keeps_defaults() {
+        let cfg = toml::from_str::<Config>(
+            "gpus = 6\n\
+             [logs]\n\
+             lines = 500\n\
+             [thresholds]\n\
+             gpu_temp_max = 85\n",
+        ).unwrap();
+        assert_eq!(cfg.gpus, 6);
+        assert_eq!(cfg.service, "miner");
+        assert_eq!(cfg.bind, "0.0.0.0");
+        assert_eq!(cfg.port, None);
+        assert_eq!(cfg.logs.lines, 500);
+        assert_eq!(cfg.logs.rules.len(), logs::default_rules().len());
+        assert_eq!(cfg.thresholds.gpu_temp_max, Some(85));
+    }
+
+    #[test]
+    fn load_example_config() {
+        let cfg = Config::load(concat!(env!("CARGO_MANIFEST_DIR"), "/healthyrig.toml")).unwrap();
+        assert_eq!(cfg.port, Some(4242));
+        // copied example keeps built-in checks
+        assert_eq!(cfg.gpus, 0);
+        assert_eq!(cfg.logs.rules.len(), logs::default_rules().len());
+
+        let e = Config::load("/nonexistent/healthyrig.toml").unwrap_err();
+        assert!(e.starts_with("Can not read config"));
+    }
+    #[test]
+    fn action_units() {
+        let mut cfg = Config::default();
+        assert_eq!(cfg.units(), vec!["miner"]);
+        assert_eq!(cfg.action_unit(None), Some(String::from("miner")));
+        assert_eq!(cfg.action_unit(Some("sshd")), None);
+
+        cfg = toml::from_str::<Config>(
+            "[[services]]
+             name = \"miner\"
+             [[services]]
+             name = \"watchdog\"
+             [[services]]
+             name = \"ethdcrminer64\"
+             kind = \"process\"
+",
+        ).unwrap();
+        assert_eq!(cfg.units(), vec!["miner", "watchdog"]);
+        assert_eq!(cfg.action_unit(Some("watchdog")), Some(String::from("watchdog")));
+        assert_eq!(cfg.action_unit(Some("ethdcrminer64")), None);
+        assert_eq!(cfg.action_unit(None), None);
+    }
+
+    #[test]
+    fn insecure_actions() {
+        let mut cfg = Config::default();
+        assert!(!cfg.disable_insecure_actions());
+
+        cfg.actions.token = Some(String::from("s3cret"));
+        cfg.bind = String::from("127.0.0.1");
+        assert!(!cfg.disable_insecure_actions());
+        assert!(cfg.actions.token.is_some());
+
+        cfg.bind = String::from("0.0.0.0");
+        cfg.key = Some(String::from("farm-key"));
+        assert!(!cfg.disable_insecure_actions());
+        assert!(cfg.actions.token.is_some());
+
+        cfg.key = None;
+        cfg.tls = Some(TlsCfg {
+            cert: String::from("/etc/healthyrig/cert.pem"),
+            key: String::from("/etc/healthyrig/key.pem"),
+        });
+        assert!(!cfg.disable_insecure_actions());
+        assert!(cfg.actions.token.is_some());
+
+        cfg.tls = None;
+        assert!(cfg.disable_insecure_actions());
+        assert_eq!(cfg.actions.token, None);
+    }
+}
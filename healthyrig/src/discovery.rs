@@ -0,0 +1,59 @@
+//! LAN discovery beacon.
+//! healthyrig announces its rig ID, hostname and port, so the controller
+//! finds the rig by them when DHCP moves it to another address.
+
+use rigauth;
+use push;
+use sampler::Sampler;
+use server::Format;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BeaconCfg {
+    /// Rig ID to announce, hostname is used if not set
+    pub id: Option<String>,
+    /// Broadcast (or controller) address as `host:port`
+    pub to: String,
+    /// Seconds between beacons
+    pub interval: u64,
+}
+
+impl Default for BeaconCfg {
+    fn default() -> BeaconCfg {
+        BeaconCfg {
+            id: None,
+            to: String::from("255.255.255.255:4244"),
+            interval: 10,
+        }
+    }
+}
+
+/// Beacon body, the controller takes rig address from datagram source
+#[derive(Debug, Serialize)]
+struct Beacon {
+    id: String,
+    hostname: String,
+    /// HTTP server port, not set in push only mode
+    port: Option<usize>,
+    scheme: &'static str,
+}
+
+/// Send beacons until shutdown is requested
+pub fn run(sampler: &Sampler) {
+    let cfg = &sampler.checker.cfg;
+    let beacon = match cfg.beacon {
+        Some(ref b) => b,
+        None => return,
+    };
+    println!("Sending discovery beacons to {} every {}s", beacon.to, beacon.interval);
+    push::send_every(&beacon.to, beacon.interval, || {
+        let hostname = sampler.latest().hostname;
+        let body = Format::Toml.serialize(&Beacon {
+            id: beacon.id.clone().unwrap_or_else(|| hostname.clone()),
+            hostname,
+            port: cfg.port,
+            scheme: if cfg.tls.is_some() { "https" } else { "http" },
+        })?;
+        Ok(push::packet("BEACON", cfg.key.as_deref(), body.as_bytes(), rigauth::now()))
+    });
+}
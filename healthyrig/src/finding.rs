@@ -0,0 +1,3 @@
+//! Findings are shared with ThorinPi, which reads them from check results.
+
+pub use rigproto::{Code, Finding, Severity};
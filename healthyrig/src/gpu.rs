@@ -0,0 +1,219 @@
+use finding::Finding;
+use nvidia;
+use sys::Host;
+
+use std::fs::read_dir;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Vendor {
+    Amd,
+    Nvidia,
+}
+
+impl Vendor {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Vendor::Amd => "amd",
+            Vendor::Nvidia => "nvidia",
+        }
+    }
+}
+
+/// Single GPU readings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gpu {
+    /// Position in GPU list, AMD cards go first
+    pub index: usize,
+    pub vendor: Vendor,
+    /// PCI bus ID like 0000:01:00.0
+    pub bus_id: Option<String>,
+    /// Temperature in Celsius, not set if it can not be read
+    pub temp: Option<i32>,
+    /// Junction (hotspot) temperature in Celsius
+    pub temp_junction: Option<i32>,
+    /// Memory temperature in Celsius
+    pub temp_mem: Option<i32>,
+    pub fan_rpm: Option<u32>,
+    /// Fan speed in percents
+    pub fan: Option<u32>,
+    /// Power draw in Watts
+    pub power: Option<f64>,
+    /// GPU utilization in percents
+    pub load: Option<u32>,
+    /// Core clock in MHz
+    pub clock_core: Option<u32>,
+    /// Memory clock in MHz
+    pub clock_mem: Option<u32>,
+    /// Active clock throttle reasons (NVIDIA only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle: Vec<String>,
+}
+
+impl Gpu {
+    pub fn new(vendor: Vendor) -> Gpu {
+        Gpu {
+            index: 0,
+            vendor,
+            bus_id: None,
+            temp: None,
+            temp_junction: None,
+            temp_mem: None,
+            fan_rpm: None,
+            fan: None,
+            power: None,
+            load: None,
+            clock_core: None,
+            clock_mem: None,
+            throttle: Vec::new(),
+        }
+    }
+}
+
+/// GPUs with temperature reading, compared with expected GPUs count
+pub fn readings(gpus: &[Gpu]) -> usize {
+    gpus.iter().filter(|g| g.temp.is_some()).count()
+}
+
+/// All detected GPUs and nvidia-smi error if it failed
+pub fn check_gpus(host: &Host, hwmon: &str) -> (Vec<Gpu>, Option<Finding>) {
+    let mut res = check_amd(host, hwmon);
+    let (nvidia, error) = nvidia::check(host);
+    res.extend(nvidia);
+    for (idx, gpu) in res.iter_mut().enumerate() {
+        gpu.index = idx;
+    }
+    (res, error)
+}
+
+/// AMD GPUs from amdgpu hwmon devices ordered by PCI bus ID
+fn check_amd(host: &Host, hwmon: &str) -> Vec<Gpu> {
+    let base = host.path(hwmon);
+    if !base.is_dir() {
+        println!("ERROR: Can not read directory {}", base.display());
+        return Vec::new();
+    }
+
+    let mut res: Vec<Gpu> = read_dir(base)
+        .unwrap()
+        .filter_map(|r| r.ok().map(|e| Path::new(hwmon).join(e.file_name())))
+        .filter(|p| {
+            host.read_string(p.join("name"))
+                .map(|s| s.contains("amdgpu"))
+                .unwrap_or(false)
+        })
+        .map(|p| read_amd(host, &p))
+        .collect();
+    res.sort_by(|a, b| a.bus_id.cmp(&b.bus_id));
+    res
+}
+
+/// Read amdgpu hwmon device.
+/// Temperatures are in millidegrees, power is in microwatts.
+fn read_amd(host: &Host, p: &Path) -> Gpu {
+    let mut gpu = Gpu::new(Vendor::Amd);
+    gpu.bus_id = host.link_name(p.join("device"));
+    gpu.temp = host.read_number::<i32, _>(p.join("temp1_input")).map(|t| t / 1000);
+    gpu.temp_junction = host.read_number::<i32, _>(p.join("temp2_input")).map(|t| t / 1000);
+    gpu.temp_mem = host.read_number::<i32, _>(p.join("temp3_input")).map(|t| t / 1000);
+    gpu.fan_rpm = host.read_number(p.join("fan1_input"));
+    gpu.fan = host.read_number::<u32, _>(p.join("pwm1")).map(|pwm| {
+        let max = host.read_number::<u32, _>(p.join("pwm1_max"))
+            .filter(|m| *m > 0)
+            .unwrap_or(255);
+        pwm * 100 / max
+    });
+    gpu.power = host.read_number::<u64, _>(p.join("power1_average"))
+        .or_else(|| host.read_number::<u64, _>(p.join("power1_input")))
+        .map(|uw| uw as f64 / 1_000_000.0);
+    gpu.load = host.read_number(p.join("device").join("gpu_busy_percent"));
+    gpu
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sys::fake::{FakeRoot, FakeRunner};
+
+    /// Add hwmon device linked to PCI device
+    fn device(root: &FakeRoot, hwmon: &str, driver: &str, bus_id: &str, files: &[(&str, &str)]) {
+        let dir = format!("/sys/class/hwmon/{}", hwmon);
+        let dev = format!("/sys/devices/pci0000:00/{}", bus_id);
+        root.write(&format!("{}/uevent", dev), "")
+            .symlink(&format!("{}/device", dir), &dev)
+            .write(&format!("{}/name", dir), &format!("{}\n", driver));
+        for &(file, content) in files {
+            root.write(&format!("{}/{}", dir, file), content);
+        }
+    }
+
+    fn check(root: &FakeRoot) -> Vec<Gpu> {
+        check_amd(&root.host(FakeRunner::default()), "/sys/class/hwmon")
+    }
+
+    #[test]
+    fn amd_ordered_by_bus_id() {
+        let root = FakeRoot::new();
+        device(&root, "hwmon0", "amdgpu", "0000:03:00.0", &[("temp1_input", "84000\n")]);
+        device(&root, "hwmon1", "amdgpu", "0000:01:00.0", &[]);
+        device(&root, "hwmon2", "k10temp", "0000:00:18.3", &[("temp1_input", "45000\n")]);
+
+        let gpus = check(&root);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].bus_id, Some(String::from("0000:01:00.0")));
+        assert_eq!(gpus[0].temp, None);
+        assert_eq!(gpus[1].bus_id, Some(String::from("0000:03:00.0")));
+        assert_eq!(gpus[1].temp, Some(84));
+        assert!(gpus.iter().all(|g| g.vendor == Vendor::Amd));
+        // card without temperature is not counted as detected
+        assert_eq!(readings(&gpus), 1);
+
+        let host = root.host(FakeRunner::default());
+        assert!(check_amd(&host, "/nonexistent/hwmon").is_empty());
+    }
+
+    #[test]
+    fn amd_sensors() {
+        let root = FakeRoot::new();
+        device(
+            &root,
+            "hwmon0",
+            "amdgpu",
+            "0000:01:00.0",
+            &[
+                ("temp1_input", "62000\n"),
+                ("temp2_input", "75000\n"),
+                ("temp3_input", "88000\n"),
+                ("fan1_input", "2100\n"),
+                ("pwm1", "153\n"),
+                ("pwm1_max", "255\n"),
+                ("power1_average", "121500000\n"),
+                ("device/gpu_busy_percent", "97\n"),
+            ],
+        );
+        // older kernels have no power average, zero PWM maximum means default
+        device(
+            &root,
+            "hwmon1",
+            "amdgpu",
+            "0000:03:00.0",
+            &[("pwm1", "255\n"), ("pwm1_max", "0\n"), ("power1_input", "80000000\n")],
+        );
+
+        let gpus = check(&root);
+        let g = &gpus[0];
+        assert_eq!((g.temp, g.temp_junction, g.temp_mem), (Some(62), Some(75), Some(88)));
+        assert_eq!(g.fan_rpm, Some(2100));
+        assert_eq!(g.fan, Some(60));
+        assert_eq!(g.power, Some(121.5));
+        assert_eq!(g.load, Some(97));
+
+        let g = &gpus[1];
+        assert_eq!((g.temp, g.fan_rpm, g.load), (None, None, None));
+        assert_eq!(g.fan, Some(100));
+        assert_eq!(g.power, Some(80.0));
+    }
+}
@@ -0,0 +1,134 @@
+//! Recent check results kept in memory and saved to disk,
+//! so the minutes before a power cycle can be looked at after reboot.
+
+use check::CheckResult;
+use serde_json;
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Ring buffer of check results ordered by time
+#[derive(Debug)]
+pub struct History {
+    samples: VecDeque<CheckResult>,
+    size: usize,
+}
+
+impl History {
+    pub fn new(size: usize) -> History {
+        History {
+            samples: VecDeque::with_capacity(size),
+            size,
+        }
+    }
+
+    /// Add sample, the oldest one is dropped when buffer is full
+    pub fn push(&mut self, r: CheckResult) {
+        if self.size == 0 {
+            return;
+        }
+        while self.samples.len() >= self.size {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(r);
+    }
+
+    /// Samples taken after unix time `since`
+    pub fn since(&self, since: u64) -> Vec<CheckResult> {
+        self.samples
+            .iter()
+            .filter(|r| r.timestamp > since)
+            .cloned()
+            .collect()
+    }
+
+    /// Load samples saved by `save`, keeps samples already in buffer
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let mut s = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut s))
+            .map_err(|e| format!("Can not read history {}: {}", path, e))?;
+        let saved: Vec<CheckResult> = serde_json::from_str(&s)
+            .map_err(|e| format!("Can not parse history {}: {}", path, e))?;
+
+        let current: Vec<CheckResult> = self.samples.drain(..).collect();
+        for r in saved.into_iter().chain(current) {
+            self.push(r);
+        }
+        Ok(())
+    }
+
+    /// Write samples to file, through temporary file so power loss never leaves it half written
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let tmp = format!("{}.tmp", path);
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Can not create {}: {}", dir.display(), e))?;
+        }
+        let json = serde_json::to_string(&self.samples).map_err(|e| e.to_string())?;
+        File::create(&tmp)
+            .and_then(|mut f| f.write_all(json.as_bytes()).and_then(|_| f.sync_all()))
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("Can not write history {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use os::OsStats;
+    use sys::fake::FakeRoot;
+
+    fn sample(timestamp: u64) -> CheckResult {
+        CheckResult {
+            hostname: String::from("rig01"),
+            timestamp,
+            age: 0,
+            temp: vec![61, 64],
+            service: true,
+            hw_errors: false,
+            services: Vec::new(),
+            gpus: Vec::new(),
+            findings: Vec::new(),
+            miner: None,
+            os: OsStats::default(),
+        }
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut h = History::new(3);
+        for ts in 1..6 {
+            h.push(sample(ts));
+        }
+        let stamps: Vec<u64> = h.since(0).iter().map(|r| r.timestamp).collect();
+        assert_eq!(stamps, vec![3, 4, 5]);
+        assert_eq!(h.since(4).len(), 1);
+        assert!(h.since(5).is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let root = FakeRoot::new();
+        let path = root.path.join("var/lib/healthyrig/history.json");
+        let path = path.to_str().unwrap();
+
+        let mut h = History::new(3);
+        h.push(sample(1));
+        h.push(sample(2));
+        h.save(path).unwrap();
+
+        // after reboot
+        let mut h = History::new(3);
+        h.push(sample(10));
+        h.push(sample(11));
+        h.load(path).unwrap();
+        let stamps: Vec<u64> = h.since(0).iter().map(|r| r.timestamp).collect();
+        assert_eq!(stamps, vec![2, 10, 11]);
+        assert_eq!(h.since(0)[0].temp, vec![61, 64]);
+
+        assert!(History::new(3).load("/nonexistent/history.json").is_err());
+    }
+}
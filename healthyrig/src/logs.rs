@@ -0,0 +1,427 @@
+use regex::{self, Regex};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+
+use finding::{Code, Finding, Severity};
+use libc;
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::time::{Duration, Instant};
+
+/// journalctl `--show-cursor` prints cursor after entries with this prefix
+static CURSOR_PREFIX: &str = "-- cursor: ";
+
+/// Log file tail read on first scan is limited to last bytes
+const MAX_TAIL_BYTES: u64 = 256 * 1024;
+
+/// `/dev/kmsg` records are read with buffer of this size, longer ones are cut by kernel
+const KMSG_RECORD_SIZE: usize = 8192;
+
+/// Named log pattern
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogRule {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+impl LogRule {
+    /// Rule that matches plain substring
+    fn literal(name: &str, text: &str, severity: Severity) -> LogRule {
+        LogRule {
+            name: String::from(name),
+            pattern: Regex::new(&regex::escape(text)).unwrap(),
+            severity,
+        }
+    }
+
+    fn regex(name: &str, pattern: &str, severity: Severity) -> LogRule {
+        LogRule {
+            name: String::from(name),
+            pattern: Regex::new(pattern).unwrap(),
+            severity,
+        }
+    }
+}
+
+/// Claymore and ethminer failure messages
+pub fn default_rules() -> Vec<LogRule> {
+    vec![
+        LogRule::literal("gpu_watchdog", "WATCHDOG: GPU error", Severity::Critical),
+        LogRule::literal("opencl_hang", "hangs in OpenCL call, exit", Severity::Critical),
+        LogRule::literal("gpuminer_failed", "GpuMiner kx failed", Severity::Critical),
+        LogRule::literal(
+            "temp_read_error",
+            "cannot get current temperature, error",
+            Severity::Critical,
+        ),
+        LogRule::literal("gpus_stopped", "are stopped. Restart attemp", Severity::Critical),
+        LogRule::literal("thread_exited", "Thread exited with code", Severity::Critical),
+        //  Miner thread hangs, need to restart miner!
+        LogRule::literal("thread_hangs", "Miner thread hangs", Severity::Critical),
+        LogRule::literal("restart_required", "need to restart miner!", Severity::Critical),
+    ]
+}
+
+/// GPU driver faults, PCIe errors and OOM killer in kernel log
+pub fn default_kernel_rules() -> Vec<LogRule> {
+    vec![
+        // amdgpu 0000:03:00.0: [drm:amdgpu_job_timedout] *ERROR* ring gfx timeout
+        LogRule::regex("amdgpu_ring_timeout", r"ring \S+ timeout", Severity::Critical),
+        LogRule::literal("amdgpu_reset_failed", "GPU reset failed", Severity::Critical),
+        LogRule::literal("amdgpu_vm_fault", "VM_L2_PROTECTION_FAULT", Severity::Warning),
+        // NVRM: Xid (PCI:0000:01:00): 79, pid=1234, GPU has fallen off the bus.
+        LogRule::literal("nvrm_fallen_off_bus", "fallen off the bus", Severity::Critical),
+        LogRule::literal("nvrm_xid", "NVRM: Xid", Severity::Warning),
+        LogRule::regex(
+            "pcie_uncorrected",
+            r"AER: (Multiple )?Uncorrected|PCIe Bus Error: severity=Uncorrected",
+            Severity::Critical,
+        ),
+        LogRule::regex(
+            "pcie_corrected",
+            r"AER: (Multiple )?Corrected|PCIe Bus Error: severity=Corrected",
+            Severity::Warning,
+        ),
+        // Out of memory: Killed process 1234 (ethdcrminer64)
+        LogRule::literal("oom_kill", "Out of memory: Kill", Severity::Warning),
+    ]
+}
+
+lazy_static! {
+    /// GPU number in miner log line, e.g. `GPU #2` or `GPU2`
+    static ref GPU_RE: Regex = Regex::new(r"GPU\s*#?(\d+)").unwrap();
+}
+
+/// Findings of `code` for matched log lines.
+/// Only the last matching line is reported for every rule and GPU pair.
+/// Finding GPU is the miner's own `GPU #n` numbering from the line,
+/// which is not always the nvidia-smi index used by other findings.
+pub fn scan(code: Code, rules: &[LogRule], logs: &str) -> Vec<Finding> {
+    let mut res: Vec<Finding> = Vec::new();
+    for line in logs.lines() {
+        for r in rules.iter().filter(|r| r.pattern.is_match(line)) {
+            let gpu = GPU_RE
+                .captures(line)
+                .and_then(|c| c[1].parse::<usize>().ok());
+            res.retain(|f| !(f.rule.as_ref() == Some(&r.name) && f.gpu == gpu));
+
+            let mut f = Finding::new(
+                code,
+                r.severity,
+                format!("Log rule {} matched", r.name),
+            );
+            f.rule = Some(r.name.clone());
+            f.line = Some(String::from(line.trim()));
+            f.gpu = gpu;
+            res.push(f);
+        }
+    }
+    res
+}
+
+/// Journal scanning progress and recently matched errors
+#[derive(Debug, Default)]
+pub struct LogState {
+    /// Cursor of the last scanned journal entry
+    pub cursor: Option<String>,
+    /// Position after the last scanned log file line
+    pub file: Option<FilePos>,
+    /// Sequence number of the last read `/dev/kmsg` record
+    pub kmsg_seq: Option<u64>,
+    seen: Vec<(Instant, Finding)>,
+}
+
+impl LogState {
+    /// Remember new findings and get all seen within `active_for` before `now`.
+    /// New finding replaces older one of the same rule and GPU.
+    pub fn update(
+        &mut self,
+        now: Instant,
+        active_for: Duration,
+        found: Vec<Finding>,
+    ) -> Vec<Finding> {
+        for f in found {
+            self.seen.retain(|(_, s)| !(s.rule == f.rule && s.gpu == f.gpu));
+            self.seen.push((now, f));
+        }
+        self.seen.retain(|&(at, _)| now.duration_since(at) <= active_for);
+        self.seen.iter().map(|(_, f)| f.clone()).collect()
+    }
+}
+
+/// Split `journalctl --show-cursor` output into entries and cursor.
+/// Cursor is not printed when there are no entries.
+pub fn split_cursor(out: &str) -> (&str, Option<&str>) {
+    let trimmed = out.trim_end();
+    match trimmed.rfind('\n').map(|i| i + 1).unwrap_or(0) {
+        i if trimmed[i..].starts_with(CURSOR_PREFIX) => (
+            &trimmed[..i],
+            Some(trimmed[i + CURSOR_PREFIX.len()..].trim()),
+        ),
+        _ => (out, None),
+    }
+}
+
+/// Position in tailed log file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilePos {
+    inode: u64,
+    offset: u64,
+}
+
+/// Complete lines appended to log file since `pos`, or last `lines` lines on first read.
+/// File is read from start when it was rotated (other inode) or truncated.
+pub fn tail(file: &mut File, pos: Option<FilePos>, lines: usize) -> io::Result<(String, FilePos)> {
+    let meta = file.metadata()?;
+    let start = match pos {
+        Some(p) if p.inode == meta.ino() && p.offset <= meta.len() => p.offset,
+        Some(_) => 0,
+        None => meta.len().saturating_sub(MAX_TAIL_BYTES),
+    };
+
+    let mut buf = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.read_to_end(&mut buf)?;
+    // partial line is left for the next read
+    let complete = buf.iter().rposition(|&b| b == b'\n').map(|i| i + 1).unwrap_or(0);
+    let mut text = String::from_utf8_lossy(&buf[..complete]).into_owned();
+
+    if pos.is_none() {
+        let skip = text.lines().count().saturating_sub(lines);
+        text = text.lines().skip(skip).collect::<Vec<&str>>().join("\n");
+    }
+    Ok((
+        text,
+        FilePos {
+            inode: meta.ino(),
+            offset: start + complete as u64,
+        },
+    ))
+}
+
+/// Kernel messages from non-blocking `/dev/kmsg` after record `after`,
+/// or last `lines` messages on first read. Also returns the last read record number.
+pub fn read_kmsg<R: Read>(
+    kmsg: &mut R,
+    after: Option<u64>,
+    lines: usize,
+) -> io::Result<(String, Option<u64>)> {
+    let mut buf = vec![0u8; KMSG_RECORD_SIZE];
+    let mut messages = Vec::new();
+    let mut last = after;
+    loop {
+        // every read returns one record, regular file (tests) returns many
+        let n = match kmsg.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            // records were overwritten in ring buffer before we read them
+            Err(ref e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(e) => return Err(e),
+        };
+        // PRIORITY,SEQUENCE,TIMESTAMP,FLAGS;MESSAGE
+        // followed by " KEY=VALUE" lines
+        for record in String::from_utf8_lossy(&buf[..n]).lines() {
+            let (prefix, message) = match record.find(';') {
+                Some(i) if !record.starts_with(' ') => (&record[..i], &record[i + 1..]),
+                _ => continue,
+            };
+            if let Some(seq) = prefix.split(',').nth(1).and_then(|s| s.parse::<u64>().ok()) {
+                if after.map_or(false, |a| seq <= a) {
+                    continue;
+                }
+                last = Some(seq);
+            }
+            messages.push(String::from(message));
+        }
+    }
+
+    if after.is_none() {
+        let skip = messages.len().saturating_sub(lines);
+        messages.drain(..skip);
+    }
+    Ok((messages.join("\n"), last))
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Regex::new(&s).map_err(D::Error::custom)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sys::fake::FakeRoot;
+    use toml;
+
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    static CLAYMORE: &str = "\
+ETH: 12/01/18-10:00:00 - New job from eth.pool.com:4444
+GPU0 t=61C fan=45%, GPU1 t=65C fan=50%
+WATCHDOG: GPU error, you need to restart miner :(
+ETH: 12/01/18-10:00:05 - SHARE FOUND - (GPU 1)
+";
+
+    static JOURNAL: &str = "\
+ETH: 12/01/18-10:00:00 - New job from eth.pool.com:4444
+GPU #2: ATI Radeon RX 580, WATCHDOG: GPU error, you need to restart miner :(
+-- cursor: s=6f1c3a;i=1a2b;b=9e8d;m=4c5d;t=57e1;x=2f3e
+";
+
+    #[derive(Deserialize)]
+    struct Rules {
+        rules: Vec<LogRule>,
+    }
+
+    fn names(found: &[Finding]) -> Vec<String> {
+        found.iter().filter_map(|f| f.rule.clone()).collect()
+    }
+
+    #[test]
+    fn default_rules_match() {
+        let rules = default_rules();
+        assert_eq!(names(&scan(Code::LogPattern, &rules, CLAYMORE)), vec!["gpu_watchdog"]);
+        assert_eq!(
+            names(&scan(Code::LogPattern, &rules, "Miner thread hangs, need to restart miner!\n")),
+            vec!["thread_hangs", "restart_required"]
+        );
+        assert!(scan(Code::LogPattern, &rules, "ETH: 12/01/18-10:00:00 - New job\n").is_empty());
+        assert!(scan(Code::LogPattern, &rules, "").is_empty());
+    }
+
+    #[test]
+    fn findings_per_gpu() {
+        let logs = "\
+GPU #2: WATCHDOG: GPU error, you need to restart miner :(
+GPU0: WATCHDOG: GPU error, first
+GPU0: WATCHDOG: GPU error, second
+";
+        let found = scan(Code::LogPattern, &default_rules(), logs);
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|f| f.code == Code::LogPattern && f.is_critical()));
+        assert_eq!(found[0].gpu, Some(2));
+        // only the last line is kept for the same rule and GPU
+        assert_eq!(found[1].gpu, Some(0));
+        assert_eq!(found[1].line, Some(String::from("GPU0: WATCHDOG: GPU error, second")));
+    }
+
+    #[test]
+    fn custom_rules() {
+        let cfg = toml::from_str::<Rules>(
+            "[[rules]]\n\
+             name = \"rejected\"\n\
+             pattern = \"(?i)share rejected\"\n\
+             severity = \"warning\"\n\
+             [[rules]]\n\
+             name = \"watchdog\"\n\
+             pattern = \"WATCHDOG: GPU \\\\w+\"\n",
+        ).unwrap();
+        assert_eq!(cfg.rules[0].severity, Severity::Warning);
+        assert_eq!(cfg.rules[1].severity, Severity::Critical);
+
+        let found = scan(Code::LogPattern, &cfg.rules, "GPU1: Share REJECTED by pool\n");
+        assert_eq!(names(&found), vec!["rejected"]);
+        assert_eq!(names(&scan(Code::LogPattern, &cfg.rules, CLAYMORE)), vec!["watchdog"]);
+
+        let bad = toml::from_str::<Rules>("[[rules]]\nname = \"x\"\npattern = \"(GPU\"\n");
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn split_journal_cursor() {
+        let (logs, cursor) = split_cursor(JOURNAL);
+        assert_eq!(logs.lines().count(), 2);
+        assert_eq!(cursor, Some("s=6f1c3a;i=1a2b;b=9e8d;m=4c5d;t=57e1;x=2f3e"));
+
+        assert_eq!(split_cursor("line without cursor\n"), ("line without cursor\n", None));
+        assert_eq!(split_cursor(""), ("", None));
+    }
+
+    #[test]
+    fn errors_expire() {
+        let start = Instant::now();
+        let active_for = Duration::from_secs(600);
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut state = LogState::default();
+
+        let found = scan(Code::LogPattern, &default_rules(), split_cursor(JOURNAL).0);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].gpu, Some(2));
+        assert_eq!(state.update(at(0), active_for, found.clone()).len(), 1);
+        assert_eq!(state.update(at(300), active_for, Vec::new()).len(), 1);
+        // seen again, window starts over
+        assert_eq!(state.update(at(500), active_for, found).len(), 1);
+        assert_eq!(state.update(at(1100), active_for, Vec::new()).len(), 1);
+        assert!(state.update(at(1101), active_for, Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn read_kmsg_records() {
+        let root = FakeRoot::new();
+        root.write(
+            "/dev/kmsg",
+            "6,1001,5000,-;amdgpu: 1 line\n\
+             3,1002,6000,-;amdgpu 0000:03:00.0: ring gfx timeout\n \
+             SUBSYSTEM=pci\n \
+             DEVICE=+pci:0000:03:00.0\n\
+             4,1003,7000,-;NVRM: Xid (PCI:0000:01:00): 31\n",
+        );
+        let read = |after| {
+            let mut f = File::open(root.path.join("dev/kmsg")).unwrap();
+            read_kmsg(&mut f, after, 2).unwrap()
+        };
+
+        let (text, seq) = read(None);
+        assert_eq!(
+            text,
+            "amdgpu 0000:03:00.0: ring gfx timeout\nNVRM: Xid (PCI:0000:01:00): 31"
+        );
+        assert_eq!(seq, Some(1003));
+        assert_eq!(read(Some(1002)), (String::from("NVRM: Xid (PCI:0000:01:00): 31"), Some(1003)));
+        assert_eq!(read(Some(1003)), (String::new(), Some(1003)));
+    }
+
+    #[test]
+    fn tail_rotated_file() {
+        let root = FakeRoot::new();
+        root.write("/var/log/miner.log", "line 1\nline 2\nline 3\n");
+        let path = root.path.join("var/log/miner.log");
+        let append = |text: &str| {
+            let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+            f.write_all(text.as_bytes()).unwrap();
+        };
+        let tail_at = |pos| tail(&mut File::open(&path).unwrap(), pos, 2).unwrap();
+
+        let (text, pos) = tail_at(None);
+        assert_eq!(text, "line 2\nline 3");
+
+        append("line 4\nline 5 partial");
+        let (text, pos) = tail_at(Some(pos));
+        assert_eq!(text, "line 4\n");
+        let (text, pos) = tail_at(Some(pos));
+        assert_eq!(text, "");
+
+        // logrotate with create: old file moved away, new one started
+        fs::rename(&path, root.path.join("var/log/miner.log.1")).unwrap();
+        root.write("/var/log/miner.log", "new 1\n");
+        let (text, pos) = tail_at(Some(pos));
+        assert_eq!(text, "new 1\n");
+
+        // logrotate with copytruncate
+        root.write("/var/log/miner.log", "");
+        append("t1\n");
+        let (text, _) = tail_at(Some(pos));
+        assert_eq!(text, "t1\n");
+    }
+}
@@ -0,0 +1,315 @@
+//! Minimal writer for Prometheus text exposition format
+
+use check::CheckResult;
+use config::Config;
+use finding::{Code, Severity};
+use gpu::{self, Gpu};
+
+use std::fmt::Write;
+
+pub static CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Default)]
+pub struct Metrics {
+    out: String,
+}
+
+impl Metrics {
+    /// Start new gauge metric family
+    pub fn gauge(&mut self, name: &str, help: &str) -> &mut Metrics {
+        self.family(name, help, "gauge")
+    }
+
+    /// Start new counter metric family, name should end with `_total`
+    pub fn counter(&mut self, name: &str, help: &str) -> &mut Metrics {
+        self.family(name, help, "counter")
+    }
+
+    fn family(&mut self, name: &str, help: &str, kind: &str) -> &mut Metrics {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        self
+    }
+
+    /// Add sample to the last metric family
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) -> &mut Metrics {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let pairs: Vec<String> = labels
+                .iter()
+                .map(|&(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", pairs.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+        self
+    }
+
+    pub fn render(self) -> String {
+        self.out
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metric name, help and value getter
+type GpuGauge = (&'static str, &'static str, fn(&Gpu) -> Option<f64>);
+
+/// Check results in Prometheus format
+pub fn render_check(cfg: &Config, r: &CheckResult) -> String {
+    let mut m = Metrics::default();
+    let gpu_gauges: &[GpuGauge] = &[
+        (
+            "healthyrig_gpu_temperature_celsius",
+            "GPU temperature in degrees Celsius.",
+            |g| g.temp.map(f64::from),
+        ),
+        (
+            "healthyrig_gpu_junction_temperature_celsius",
+            "GPU junction (hotspot) temperature in degrees Celsius.",
+            |g| g.temp_junction.map(f64::from),
+        ),
+        (
+            "healthyrig_gpu_memory_temperature_celsius",
+            "GPU memory temperature in degrees Celsius.",
+            |g| g.temp_mem.map(f64::from),
+        ),
+        (
+            "healthyrig_gpu_fan_rpm",
+            "GPU fan speed in RPM.",
+            |g| g.fan_rpm.map(f64::from),
+        ),
+        (
+            "healthyrig_gpu_fan_percent",
+            "GPU fan speed in percents.",
+            |g| g.fan.map(f64::from),
+        ),
+        (
+            "healthyrig_gpu_power_watts",
+            "GPU power draw in Watts.",
+            |g| g.power,
+        ),
+        (
+            "healthyrig_gpu_load_percent",
+            "GPU utilization in percents.",
+            |g| g.load.map(f64::from),
+        ),
+        (
+            "healthyrig_gpu_core_clock_mhz",
+            "GPU core clock in MHz.",
+            |g| g.clock_core.map(f64::from),
+        ),
+        (
+            "healthyrig_gpu_memory_clock_mhz",
+            "GPU memory clock in MHz.",
+            |g| g.clock_mem.map(f64::from),
+        ),
+    ];
+    for &(name, help, value) in gpu_gauges {
+        m.gauge(name, help);
+        for g in &r.gpus {
+            if let Some(v) = value(g) {
+                m.sample(
+                    name,
+                    &[
+                        ("gpu", &g.index.to_string()),
+                        ("vendor", g.vendor.name()),
+                        ("bus_id", g.bus_id.as_deref().unwrap_or("")),
+                    ],
+                    v,
+                );
+            }
+        }
+    }
+
+    m.gauge("healthyrig_service_up", "Whether the monitored service or process is active.");
+    for s in &r.services {
+        m.sample(
+            "healthyrig_service_up",
+            &[
+                ("service", &s.name),
+                ("kind", s.kind.name()),
+                ("required", if s.required { "true" } else { "false" }),
+            ],
+            s.active as u8 as f64,
+        );
+    }
+    m.gauge("healthyrig_services_ok", "Whether all required services are active.")
+        .sample("healthyrig_services_ok", &[], r.service as u8 as f64);
+    m.gauge("healthyrig_hw_errors", "Whether hardware errors were detected.")
+        .sample(
+            "healthyrig_hw_errors",
+            &[],
+            r.hw_errors as u8 as f64,
+        );
+
+    m.gauge("healthyrig_findings", "Detected problems count by code and severity.");
+    for code in Code::all() {
+        for severity in &[Severity::Info, Severity::Warning, Severity::Critical] {
+            let count = r
+                .findings
+                .iter()
+                .filter(|f| f.code == *code && f.severity == *severity)
+                .count();
+            m.sample(
+                "healthyrig_findings",
+                &[("code", code.name()), ("severity", severity.name())],
+                count as f64,
+            );
+        }
+    }
+
+    m.gauge("healthyrig_gpus_expected", "Configured GPUs count, 0 if not set.")
+        .sample("healthyrig_gpus_expected", &[], cfg.gpus as f64);
+    m.gauge("healthyrig_gpus_detected", "Detected GPUs with temperature reading count.")
+        .sample("healthyrig_gpus_detected", &[], gpu::readings(&r.gpus) as f64);
+
+    let os = &r.os;
+    if !os.load.is_empty() {
+        m.gauge("healthyrig_load_average", "Load average by period in minutes.");
+        for (load, minutes) in os.load.iter().zip(&["1", "5", "15"]) {
+            m.sample("healthyrig_load_average", &[("minutes", minutes)], *load);
+        }
+    }
+    let os_gauges = [
+        (
+            "healthyrig_cpu_temperature_celsius",
+            "Highest thermal zone temperature in degrees Celsius.",
+            os.cpu_temp.map(f64::from),
+        ),
+        ("healthyrig_memory_total_bytes", "Total memory.", os.mem_total.map(|v| v as f64)),
+        (
+            "healthyrig_memory_available_bytes",
+            "Memory available for new programs.",
+            os.mem_available.map(|v| v as f64),
+        ),
+        ("healthyrig_swap_total_bytes", "Total swap.", os.swap_total.map(|v| v as f64)),
+        ("healthyrig_swap_free_bytes", "Free swap.", os.swap_free.map(|v| v as f64)),
+        (
+            "healthyrig_root_fs_size_bytes",
+            "Root filesystem size.",
+            os.disk_total.map(|v| v as f64),
+        ),
+        (
+            "healthyrig_root_fs_free_bytes",
+            "Root filesystem space available to unprivileged users.",
+            os.disk_free.map(|v| v as f64),
+        ),
+        ("healthyrig_uptime_seconds", "Seconds since boot.", os.uptime.map(|v| v as f64)),
+    ];
+    for &(name, help, value) in &os_gauges {
+        if let Some(v) = value {
+            m.gauge(name, help).sample(name, &[], v);
+        }
+    }
+
+    if let Some(ref miner) = cfg.miner {
+        let kind = miner.kind.name();
+        m.gauge("healthyrig_miner_up", "Whether miner API answers.")
+            .sample("healthyrig_miner_up", &[("miner", kind)], r.miner.is_some() as u8 as f64);
+    }
+    if let Some(ref s) = r.miner {
+        let kind = s.kind.name();
+        m.gauge("healthyrig_miner_hashrate", "Miner total hashrate in H/s.")
+            .sample("healthyrig_miner_hashrate", &[("miner", kind)], s.hashrate);
+        m.gauge("healthyrig_miner_gpu_hashrate", "Miner hashrate per GPU in H/s, miner GPU order.");
+        for (i, h) in s.gpus.iter().enumerate() {
+            m.sample(
+                "healthyrig_miner_gpu_hashrate",
+                &[("miner", kind), ("gpu", &i.to_string())],
+                *h,
+            );
+        }
+        m.counter("healthyrig_miner_shares_total", "Shares submitted by miner by result.")
+            .sample(
+                "healthyrig_miner_shares_total",
+                &[("miner", kind), ("result", "accepted")],
+                s.accepted as f64,
+            )
+            .sample(
+                "healthyrig_miner_shares_total",
+                &[("miner", kind), ("result", "rejected")],
+                s.rejected as f64,
+            );
+    }
+
+    m.render()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpu::Vendor;
+    use miner::{MinerKind, MinerStats};
+    use os::OsStats;
+
+    #[test]
+    fn render_families() {
+        let mut m = Metrics::default();
+        m.gauge("healthyrig_gpu_temperature_celsius", "GPU temperature.")
+            .sample("healthyrig_gpu_temperature_celsius", &[("gpu", "0")], 61.0)
+            .sample("healthyrig_gpu_temperature_celsius", &[("gpu", "1")], 65.5);
+        m.gauge("healthyrig_hw_errors", "Hardware errors.")
+            .sample("healthyrig_hw_errors", &[], 0.0);
+        assert_eq!(
+            m.render(),
+            "# HELP healthyrig_gpu_temperature_celsius GPU temperature.\n\
+             # TYPE healthyrig_gpu_temperature_celsius gauge\n\
+             healthyrig_gpu_temperature_celsius{gpu=\"0\"} 61\n\
+             healthyrig_gpu_temperature_celsius{gpu=\"1\"} 65.5\n\
+             # HELP healthyrig_hw_errors Hardware errors.\n\
+             # TYPE healthyrig_hw_errors gauge\n\
+             healthyrig_hw_errors 0\n"
+        );
+    }
+
+    #[test]
+    fn render_check_result() {
+        let mut hot = Gpu::new(Vendor::Amd);
+        hot.temp = Some(84);
+        let mut lost = Gpu::new(Vendor::Nvidia);
+        lost.index = 1;
+        let r = CheckResult {
+            hostname: String::from("rig01"),
+            timestamp: 1_543_658_400,
+            age: 0,
+            temp: vec![84],
+            service: true,
+            hw_errors: false,
+            services: Vec::new(),
+            gpus: vec![hot, lost],
+            findings: Vec::new(),
+            miner: Some(MinerStats {
+                kind: MinerKind::Claymore,
+                version: String::from("11.9"),
+                uptime: 60,
+                hashrate: 30e6,
+                gpus: Vec::new(),
+                accepted: 12,
+                rejected: 1,
+            }),
+            os: OsStats::default(),
+        };
+        let out = render_check(&Config::default(), &r);
+
+        // same count as compared with expected GPUs
+        assert!(out.contains("\nhealthyrig_gpus_detected 1\n"));
+        assert!(out.contains("\n# TYPE healthyrig_miner_shares_total counter\n"));
+        assert!(out.contains(
+            "\nhealthyrig_miner_shares_total{miner=\"claymore\",result=\"rejected\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn escape_label_values() {
+        let mut m = Metrics::default();
+        m.sample("up", &[("service", "a\"b\\c\nd"), ("kind", "unit")], 1.0);
+        assert_eq!(m.render(), "up{service=\"a\\\"b\\\\c\\nd\",kind=\"unit\"} 1\n");
+    }
+}
@@ -0,0 +1,348 @@
+//! Local miner stats API clients.
+//! Claymore, Phoenix and ethminer answer `miner_getstat1` JSON-RPC on raw TCP,
+//! xmrig serves JSON summary over HTTP.
+
+use serde_json::{self, Value};
+
+use finding::Severity;
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Connect, read and write timeout of miner API calls
+const TIMEOUT_SECS: u64 = 3;
+
+/// Reject ratio is not judged on fewer shares in window
+const MIN_WINDOW_SHARES: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MinerKind {
+    /// Claymore and Phoenix miners
+    Claymore,
+    Ethminer,
+    Xmrig,
+}
+
+impl MinerKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            MinerKind::Claymore => "claymore",
+            MinerKind::Ethminer => "ethminer",
+            MinerKind::Xmrig => "xmrig",
+        }
+    }
+
+    /// API port miners use out of the box
+    pub fn default_port(&self) -> u16 {
+        match *self {
+            MinerKind::Claymore | MinerKind::Ethminer => 3333,
+            MinerKind::Xmrig => 8080,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct MinerCfg {
+    pub kind: MinerKind,
+    pub host: String,
+    /// API port, miner default if not set
+    pub port: Option<u16>,
+    /// Claymore API password or xmrig access token
+    pub password: Option<String>,
+    /// Expected rig hashrate in H/s
+    pub expected_hashrate: Option<f64>,
+    /// Expected hashrate of every GPU in H/s, GPUs disabled in miner are not judged
+    pub expected_gpu_hashrate: Option<f64>,
+    /// Severity of a single GPU hashrate shortfall
+    pub gpu_hashrate_severity: Severity,
+    /// Report hashrate lower than expected by more percents
+    pub hashrate_tolerance: f64,
+    /// Do not judge hashrate of just started miner, seconds
+    pub warmup: u64,
+    /// Report rejected shares ratio above percents
+    pub reject_ratio_max: Option<f64>,
+    /// Reject ratio sliding window, seconds
+    pub share_window: u64,
+}
+
+impl Default for MinerCfg {
+    fn default() -> MinerCfg {
+        MinerCfg {
+            kind: MinerKind::Claymore,
+            host: String::from("127.0.0.1"),
+            port: None,
+            password: None,
+            expected_hashrate: None,
+            expected_gpu_hashrate: None,
+            gpu_hashrate_severity: Severity::Warning,
+            hashrate_tolerance: 20.0,
+            warmup: 300,
+            reject_ratio_max: None,
+            share_window: 3600,
+        }
+    }
+}
+
+/// Miner stats, hashrates are in H/s
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinerStats {
+    pub kind: MinerKind,
+    pub version: String,
+    /// Miner uptime in seconds
+    pub uptime: u64,
+    pub hashrate: f64,
+    /// Per-GPU hashrate in miner order, 0 for disabled GPU, empty if miner does not report it
+    pub gpus: Vec<f64>,
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+/// Accepted and rejected shares counters over sliding time window
+#[derive(Debug, Default)]
+pub struct ShareWindow {
+    samples: VecDeque<(Instant, u64, u64)>,
+}
+
+impl ShareWindow {
+    /// Add counters sample and get rejected shares percent in window.
+    /// Counters going back mean miner restart, window starts over then.
+    pub fn push(
+        &mut self,
+        now: Instant,
+        window: Duration,
+        accepted: u64,
+        rejected: u64,
+    ) -> Option<f64> {
+        if let Some(&(_, a, r)) = self.samples.back() {
+            if accepted < a || rejected < r {
+                self.samples.clear();
+            }
+        }
+        self.samples.push_back((now, accepted, rejected));
+        while self.samples.len() > 1 && now.duration_since(self.samples[0].0) > window {
+            self.samples.pop_front();
+        }
+
+        let (_, a0, r0) = self.samples[0];
+        let (accepted, rejected) = (accepted - a0, rejected - r0);
+        if accepted + rejected < MIN_WINDOW_SHARES {
+            return None;
+        }
+        Some(rejected as f64 * 100.0 / (accepted + rejected) as f64)
+    }
+}
+
+/// Query miner stats API
+pub fn check(cfg: &MinerCfg) -> Result<MinerStats, String> {
+    let port = cfg.port.unwrap_or_else(|| cfg.kind.default_port());
+    let mut stream = connect(&cfg.host, port).map_err(|e| {
+        format!(
+            "Can not connect to {} API at {}:{}: {}",
+            cfg.kind.name(),
+            cfg.host,
+            port,
+            e
+        )
+    })?;
+
+    match cfg.kind {
+        MinerKind::Claymore | MinerKind::Ethminer => {
+            let mut request = json!({"id": 0, "jsonrpc": "2.0", "method": "miner_getstat1"});
+            if let Some(ref psw) = cfg.password {
+                request["psw"] = json!(psw);
+            }
+            writeln!(stream, "{}", request).map_err(|e| e.to_string())?;
+            // ethminer keeps connection open, response is single line
+            let mut response = String::new();
+            BufReader::new(stream)
+                .read_line(&mut response)
+                .map_err(|e| e.to_string())?;
+            parse_getstat1(cfg.kind, &response)
+        }
+        MinerKind::Xmrig => {
+            let mut request = format!("GET /1/summary HTTP/1.0\r\nHost: {}:{}\r\n", cfg.host, port);
+            if let Some(ref token) = cfg.password {
+                request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+            }
+            request.push_str("\r\n");
+            stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).map_err(|e| e.to_string())?;
+            parse_http(&response).and_then(parse_xmrig)
+        }
+    }
+}
+
+fn connect(host: &str, port: u16) -> ::std::io::Result<TcpStream> {
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    let mut last_err = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        ::std::io::Error::new(::std::io::ErrorKind::NotFound, "no address")
+    }))
+}
+
+/// Parse `miner_getstat1` response.
+/// Result is array of strings: version, uptime in minutes, "kH/s;accepted;rejected",
+/// "gpu0 kH/s;gpu1 kH/s;..." and more fields we do not use.
+pub fn parse_getstat1(kind: MinerKind, response: &str) -> Result<MinerStats, String> {
+    let v: Value = serde_json::from_str(response.trim())
+        .map_err(|e| format!("Can not parse {} API response: {}", kind.name(), e))?;
+    let result: Vec<&str> = v["result"]
+        .as_array()
+        .ok_or_else(|| format!("No result in {} API response: {}", kind.name(), v["error"]))?
+        .iter()
+        .map(|r| r.as_str().unwrap_or(""))
+        .collect();
+    if result.len() < 4 {
+        return Err(format!("Short {} API result: {:?}", kind.name(), result));
+    }
+
+    let totals: Vec<u64> = result[2]
+        .split(';')
+        .map(|v| v.trim().parse::<u64>().unwrap_or(0))
+        .collect();
+    let khs = |v: &str| v.trim().parse::<f64>().map(|h| h * 1000.0).unwrap_or(0.0);
+
+    Ok(MinerStats {
+        kind,
+        version: String::from(result[0]),
+        uptime: result[1].trim().parse::<u64>().unwrap_or(0) * 60,
+        hashrate: totals.first().map(|&h| h as f64 * 1000.0).unwrap_or(0.0),
+        gpus: result[3]
+            .split(';')
+            .filter(|v| !v.is_empty())
+            .map(khs)
+            .collect(),
+        accepted: totals.get(1).cloned().unwrap_or(0),
+        rejected: totals.get(2).cloned().unwrap_or(0),
+    })
+}
+
+/// Body of successful HTTP response
+fn parse_http(response: &str) -> Result<&str, String> {
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| String::from("Malformed xmrig API response"))?;
+    let status = head.lines().next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("xmrig API error: {}", status));
+    }
+    Ok(body)
+}
+
+/// Parse xmrig `/1/summary` response.
+/// Hashrates are [10s, 60s, 15m] averages, the shortest one that is known is used.
+/// Summary threads are CPU or backend threads rather than GPUs, so per-GPU hashrate is left empty.
+pub fn parse_xmrig(body: &str) -> Result<MinerStats, String> {
+    let v: Value = serde_json::from_str(body)
+        .map_err(|e| format!("Can not parse xmrig API response: {}", e))?;
+    let hashrate = |rates: &Value| {
+        rates
+            .as_array()
+            .and_then(|a| a.iter().filter_map(|h| h.as_f64()).next())
+            .unwrap_or(0.0)
+    };
+    let good = v["results"]["shares_good"].as_u64().unwrap_or(0);
+    let total = v["results"]["shares_total"].as_u64().unwrap_or(0);
+
+    Ok(MinerStats {
+        kind: MinerKind::Xmrig,
+        version: String::from(v["version"].as_str().unwrap_or("")),
+        uptime: v["uptime"].as_u64().unwrap_or(0),
+        hashrate: hashrate(&v["hashrate"]["total"]),
+        gpus: Vec::new(),
+        accepted: good,
+        rejected: total.saturating_sub(good),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Claymore 11.9 with six RX 580 cards
+    static CLAYMORE: &str = r#"{"id": 0, "error": null, "result": ["11.9 - ETH", "1358", "182724;5163;2", "30502;30457;30297;30481;30479;30505", "0;0;0", "off;off;off;off;off;off", "53;71;57;67;61;72;55;70;59;71;61;70", "eu1.ethermine.org:4444", "0;0;0;0"]}"#;
+
+    /// Claymore refuses wrong password
+    static CLAYMORE_DENIED: &str = r#"{"id": 0, "error": "Invalid password", "result": null}"#;
+
+    static XMRIG: &str = r#"{
+        "id": "92f3104f9a2ee78c",
+        "version": "2.8.3",
+        "kind": "amd",
+        "hashrate": {
+            "total": [1845.6, 1850.2, null],
+            "highest": 1861.0,
+            "threads": [[921.1, 925.0, null], [924.5, 925.2, null]]
+        },
+        "results": {
+            "diff_current": 120001,
+            "shares_good": 318,
+            "shares_total": 321
+        },
+        "uptime": 7421
+    }"#;
+
+    #[test]
+    fn parse_claymore() {
+        let s = parse_getstat1(MinerKind::Claymore, CLAYMORE).unwrap();
+        assert_eq!(s.version, "11.9 - ETH");
+        assert_eq!(s.uptime, 1358 * 60);
+        assert_eq!(s.hashrate, 182_724_000.0);
+        assert_eq!(s.gpus.len(), 6);
+        assert_eq!(s.gpus[1], 30_457_000.0);
+        assert_eq!(s.accepted, 5163);
+        assert_eq!(s.rejected, 2);
+
+        assert!(parse_getstat1(MinerKind::Claymore, CLAYMORE_DENIED).is_err());
+        assert!(parse_getstat1(MinerKind::Ethminer, "").is_err());
+    }
+
+    #[test]
+    fn parse_xmrig_summary() {
+        let response = format!(
+            "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{}",
+            XMRIG
+        );
+        let s = parse_http(&response).and_then(parse_xmrig).unwrap();
+        assert_eq!(s.version, "2.8.3");
+        assert_eq!(s.uptime, 7421);
+        assert_eq!(s.hashrate, 1845.6);
+        assert!(s.gpus.is_empty());
+        assert_eq!(s.accepted, 318);
+        assert_eq!(s.rejected, 3);
+
+        assert!(parse_http("HTTP/1.0 401 Unauthorized\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn share_window_ratio() {
+        let start = Instant::now();
+        let window = Duration::from_secs(600);
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut w = ShareWindow::default();
+
+        assert_eq!(w.push(at(0), window, 1000, 10), None);
+        assert_eq!(w.push(at(300), window, 1005, 10), None);
+        assert_eq!(w.push(at(600), window, 1090, 20), Some(10.0));
+        // first sample left the window
+        assert_eq!(w.push(at(900), window, 1090, 25), Some(15.0));
+        // miner restarted
+        assert_eq!(w.push(at(1000), window, 3, 0), None);
+        assert_eq!(w.push(at(1100), window, 21, 2), Some(10.0));
+    }
+}
@@ -0,0 +1,210 @@
+//! NVIDIA GPUs readings from single nvidia-smi call
+
+use finding::{Code, Finding, Severity};
+use gpu::{Gpu, Vendor};
+use sys::Host;
+
+/// Fields order matters, see `parse_csv`
+pub static QUERY: &str = "--query-gpu=index,pci.bus_id,temperature.gpu,fan.speed,power.draw,\
+                      utilization.gpu,clocks.sm,clocks.mem,clocks_throttle_reasons.active";
+pub static FORMAT: &str = "--format=csv,noheader,nounits";
+
+/// Bits of clocks_throttle_reasons.active mask
+static THROTTLE_REASONS: &[(u64, &str)] = &[
+    (0x1, "gpu_idle"),
+    (0x2, "applications_clocks_setting"),
+    (0x4, "sw_power_cap"),
+    (0x8, "hw_slowdown"),
+    (0x10, "sync_boost"),
+    (0x20, "sw_thermal_slowdown"),
+    (0x40, "hw_thermal_slowdown"),
+    (0x80, "hw_power_brake_slowdown"),
+    (0x100, "display_clock_setting"),
+];
+
+/// NVIDIA GPUs in nvidia-smi order, empty if nvidia-smi is not available
+pub fn check(host: &Host) -> (Vec<Gpu>, Option<Finding>) {
+    match host.run("nvidia-smi", &[QUERY, FORMAT]) {
+        Ok(out) => parse_output(out.success, &out.stdout, &out.stderr),
+        Err(_) => (Vec::new(), None),
+    }
+}
+
+/// nvidia-smi exits with error when a card is lost, the rest are still read
+/// and the error is returned as finding.
+fn parse_output(success: bool, stdout: &str, stderr: &str) -> (Vec<Gpu>, Option<Finding>) {
+    let gpus = parse_csv(stdout);
+    if success {
+        return (gpus, None);
+    }
+    let detail = match stderr.trim() {
+        "" => stdout
+            .lines()
+            .map(|l| l.trim())
+            .find(|l| !l.is_empty() && parse_line(l).is_none())
+            .unwrap_or("exited with error"),
+        stderr => stderr,
+    };
+    let finding = Finding::new(
+        Code::NvidiaSmi,
+        Severity::Warning,
+        format!("nvidia-smi failed: {}", detail),
+    );
+    (gpus, Some(finding))
+}
+
+/// Parse `nvidia-smi --query-gpu=... --format=csv,noheader,nounits` output
+pub fn parse_csv(out: &str) -> Vec<Gpu> {
+    out.lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Gpu> {
+    let values: Vec<Option<&str>> = line
+        .split(',')
+        .map(|v| v.trim())
+        .map(|v| if v.is_empty() || v.starts_with('[') { None } else { Some(v) })
+        .collect();
+    if values.len() < 9 {
+        return None;
+    }
+
+    let mut gpu = Gpu::new(Vendor::Nvidia);
+    gpu.index = values[0]?.parse::<usize>().ok()?;
+    gpu.bus_id = values[1].map(normalize_bus_id);
+    gpu.temp = values[2].and_then(|v| v.parse::<i32>().ok());
+    gpu.fan = values[3].and_then(|v| v.parse::<u32>().ok());
+    gpu.power = values[4].and_then(|v| v.parse::<f64>().ok());
+    gpu.load = values[5].and_then(|v| v.parse::<u32>().ok());
+    gpu.clock_core = values[6].and_then(|v| v.parse::<u32>().ok());
+    gpu.clock_mem = values[7].and_then(|v| v.parse::<u32>().ok());
+    gpu.throttle = values[8]
+        .and_then(|v| u64::from_str_radix(v.trim_start_matches("0x"), 16).ok())
+        .map(|mask| {
+            THROTTLE_REASONS
+                .iter()
+                .filter(|&&(bit, _)| mask & bit != 0)
+                .map(|&(_, name)| String::from(name))
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(gpu)
+}
+
+/// nvidia-smi reports bus ID as 00000000:01:00.0, sysfs uses 0000:01:00.0
+fn normalize_bus_id(id: &str) -> String {
+    let id = id.to_lowercase();
+    match id.split_once(':') {
+        Some((domain, rest)) if domain.len() > 4 => {
+            format!("{}:{}", &domain[domain.len() - 4..], rest)
+        }
+        _ => id,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GTX 1070 and P106-100 mining rig
+    static MINING_RIG: &str = "\
+0, 00000000:01:00.0, 61, 75, 112.34, 100, 1835, 4006, 0x0000000000000000
+1, 00000000:02:00.0, 65, 80, 119.87, 99, 1822, 4006, 0x0000000000000004
+2, 00000000:05:00.0, 58, [N/A], 98.50, 100, 1771, 4006, 0x0000000000000044
+";
+
+    /// Card that fell off the bus
+    static LOST_CARD: &str = "\
+0, 00000000:01:00.0, 61, 75, 112.34, 100, 1835, 4006, 0x0000000000000000
+1, 00000000:03:00.0, [Unknown Error], [Unknown Error], [Unknown Error], [Unknown Error], [Unknown Error], [Unknown Error], [Unknown Error]
+";
+
+    /// nvidia-smi prints this when driver is not loaded
+    static NO_DRIVER: &str = "\
+NVIDIA-SMI has failed because it couldn't communicate with the NVIDIA driver. \
+Make sure that the latest NVIDIA driver is installed and running.
+";
+
+    #[test]
+    fn parse_mining_rig() {
+        let gpus = parse_csv(MINING_RIG);
+        assert_eq!(gpus.len(), 3);
+
+        let g = &gpus[1];
+        assert_eq!(g.index, 1);
+        assert_eq!(g.vendor, Vendor::Nvidia);
+        assert_eq!(g.bus_id, Some(String::from("0000:02:00.0")));
+        assert_eq!(g.temp, Some(65));
+        assert_eq!(g.fan, Some(80));
+        assert_eq!(g.power, Some(119.87));
+        assert_eq!(g.load, Some(99));
+        assert_eq!(g.clock_core, Some(1822));
+        assert_eq!(g.clock_mem, Some(4006));
+        assert_eq!(g.throttle, vec![String::from("sw_power_cap")]);
+
+        assert!(gpus[0].throttle.is_empty());
+        assert_eq!(gpus[2].fan, None);
+        assert_eq!(
+            gpus[2].throttle,
+            vec![
+                String::from("sw_power_cap"),
+                String::from("hw_thermal_slowdown"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_lost_card() {
+        let gpus = parse_csv(LOST_CARD);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].temp, Some(61));
+
+        let g = &gpus[1];
+        assert_eq!(g.bus_id, Some(String::from("0000:03:00.0")));
+        assert_eq!(g.temp, None);
+        assert_eq!(g.power, None);
+        assert!(g.throttle.is_empty());
+    }
+
+    #[test]
+    fn parse_failed_output() {
+        assert!(parse_csv(NO_DRIVER).is_empty());
+        assert!(parse_csv("").is_empty());
+    }
+
+    #[test]
+    fn read_gpus_on_error_exit() {
+        let out = format!(
+            "{}Unable to determine the device handle for GPU 0000:06:00.0: Unknown Error\n",
+            MINING_RIG
+        );
+        let (gpus, error) = parse_output(false, &out, "");
+        assert_eq!(gpus.len(), 3);
+        let error = error.unwrap();
+        assert_eq!(error.code, Code::NvidiaSmi);
+        assert_eq!(error.severity, Severity::Warning);
+        assert_eq!(
+            error.message,
+            "nvidia-smi failed: \
+             Unable to determine the device handle for GPU 0000:06:00.0: Unknown Error"
+        );
+
+        let (gpus, error) = parse_output(false, "", "NVIDIA-SMI has failed\n");
+        assert!(gpus.is_empty());
+        assert_eq!(error.unwrap().message, "nvidia-smi failed: NVIDIA-SMI has failed");
+
+        let (gpus, error) = parse_output(true, MINING_RIG, "");
+        assert_eq!(gpus.len(), 3);
+        assert!(error.is_none());
+    }
+
+    #[test]
+    fn normalize_nvidia_bus_id() {
+        assert_eq!(normalize_bus_id("00000000:0A:00.0"), "0000:0a:00.0");
+        assert_eq!(normalize_bus_id("0000:01:00.0"), "0000:01:00.0");
+        assert_eq!(normalize_bus_id("01:00.0"), "01:00.0");
+    }
+}
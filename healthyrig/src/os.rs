@@ -0,0 +1,143 @@
+//! Host OS readings from `/proc` and `/sys`
+
+use sys::Host;
+
+use std::fs::read_dir;
+
+/// OS health, fields are not set if they can not be read
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OsStats {
+    /// 1, 5 and 15 minutes load average
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub load: Vec<f64>,
+    /// Highest thermal zone temperature in Celsius
+    pub cpu_temp: Option<i32>,
+    /// Memory and swap sizes in bytes
+    pub mem_total: Option<u64>,
+    pub mem_available: Option<u64>,
+    pub swap_total: Option<u64>,
+    pub swap_free: Option<u64>,
+    /// Root filesystem size and space available to unprivileged users in bytes
+    pub disk_total: Option<u64>,
+    pub disk_free: Option<u64>,
+    /// Seconds since boot
+    pub uptime: Option<u64>,
+    /// Changes on every boot
+    pub boot_id: Option<String>,
+}
+
+impl OsStats {
+    /// Available memory percent
+    pub fn mem_available_percent(&self) -> Option<f64> {
+        percent(self.mem_available, self.mem_total)
+    }
+
+    /// Free root filesystem space percent
+    pub fn disk_free_percent(&self) -> Option<f64> {
+        percent(self.disk_free, self.disk_total)
+    }
+}
+
+fn percent(part: Option<u64>, total: Option<u64>) -> Option<f64> {
+    match (part, total) {
+        (Some(p), Some(t)) if t > 0 => Some(p as f64 * 100.0 / t as f64),
+        _ => None,
+    }
+}
+
+pub fn check(host: &Host) -> OsStats {
+    let mut os = OsStats {
+        load: host
+            .read_string("/proc/loadavg")
+            .map(|s| {
+                s.split_whitespace()
+                    .take(3)
+                    .filter_map(|v| v.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        cpu_temp: cpu_temp(host),
+        uptime: host
+            .read_string("/proc/uptime")
+            .and_then(|s| s.split_whitespace().next().and_then(|v| v.parse::<f64>().ok()))
+            .map(|v| v as u64),
+        boot_id: host
+            .read_string("/proc/sys/kernel/random/boot_id")
+            .map(|s| String::from(s.trim())),
+        ..OsStats::default()
+    };
+
+    if let Some(meminfo) = host.read_string("/proc/meminfo") {
+        for line in meminfo.lines() {
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap_or("");
+            let kb = match parts.next().and_then(|v| v.parse::<u64>().ok()) {
+                Some(kb) => kb,
+                None => continue,
+            };
+            let field = match name {
+                "MemTotal:" => &mut os.mem_total,
+                "MemAvailable:" => &mut os.mem_available,
+                "SwapTotal:" => &mut os.swap_total,
+                "SwapFree:" => &mut os.swap_free,
+                _ => continue,
+            };
+            *field = Some(kb * 1024);
+        }
+    }
+
+    match host.disk_space("/") {
+        Ok((total, free)) => {
+            os.disk_total = Some(total);
+            os.disk_free = Some(free);
+        }
+        Err(e) => println!("ERROR: Can not get root filesystem space: {}", e),
+    }
+    os
+}
+
+/// Highest temperature among thermal zones
+fn cpu_temp(host: &Host) -> Option<i32> {
+    let zones = read_dir(host.path("/sys/class/thermal")).ok()?;
+    zones
+        .filter_map(|z| z.ok())
+        .map(|z| z.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("thermal_zone"))
+        .filter_map(|name| host.read_number::<i32, _>(format!("/sys/class/thermal/{}/temp", name)))
+        .map(|t| t / 1000)
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sys::fake::{FakeRoot, FakeRunner};
+
+    #[test]
+    fn read_os_stats() {
+        let root = FakeRoot::new();
+        root.write("/proc/loadavg", "0.52 0.58 1.59 1/467 12345\n")
+            .write("/proc/uptime", "12345.67 40000.12\n")
+            .write("/proc/sys/kernel/random/boot_id", "0e2a8d2c-5b7e-4c2e-9f3a-1d2b3c4d5e6f\n")
+            .write(
+                "/proc/meminfo",
+                "MemTotal:        8052604 kB\nMemFree:          201392 kB\n\
+                 MemAvailable:     402630 kB\nSwapTotal:       2097148 kB\n\
+                 SwapFree:        2097148 kB\n",
+            )
+            .write("/sys/class/thermal/thermal_zone0/temp", "27800\n")
+            .write("/sys/class/thermal/thermal_zone1/temp", "61000\n")
+            .write("/sys/class/thermal/cooling_device0/cur_state", "0\n");
+        let os = check(&root.host(FakeRunner::default()));
+
+        assert_eq!(os.load, vec![0.52, 0.58, 1.59]);
+        assert_eq!(os.cpu_temp, Some(61));
+        assert_eq!(os.uptime, Some(12345));
+        assert_eq!(os.boot_id.as_ref().unwrap(), "0e2a8d2c-5b7e-4c2e-9f3a-1d2b3c4d5e6f");
+        assert_eq!(os.mem_total, Some(8_052_604 * 1024));
+        assert_eq!(os.swap_free, Some(2_097_148 * 1024));
+        assert_eq!(os.mem_available_percent().map(|p| p.round()), Some(5.0));
+        assert!(os.disk_total.unwrap() > 0);
+        assert!(os.disk_free_percent().is_some());
+    }
+}
@@ -0,0 +1,110 @@
+//! Push mode: check results are sent to the controller as UDP heartbeats,
+//! so it does not have to poll every rig.
+//!
+//! Datagram is `TIMESTAMP SIGNATURE\n` line followed by TOML body (check result here),
+//! signature is `-` when no shared key is set.
+
+use rigauth;
+use sampler::Sampler;
+use server::Format;
+use sys;
+
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PushCfg {
+    /// Controller address as `host:port`
+    pub to: String,
+    /// Seconds between heartbeats
+    pub interval: u64,
+}
+
+impl Default for PushCfg {
+    fn default() -> PushCfg {
+        PushCfg {
+            to: String::new(),
+            interval: 10,
+        }
+    }
+}
+
+/// Send heartbeats until shutdown is requested
+pub fn run(sampler: &Sampler) {
+    let cfg = match sampler.checker.cfg.push {
+        Some(ref p) => p,
+        None => return,
+    };
+    println!("Sending heartbeats to {} every {}s", cfg.to, cfg.interval);
+    let key = sampler.checker.cfg.key.as_deref();
+    send_every(&cfg.to, cfg.interval, || {
+        let body = Format::Toml.serialize(&sampler.latest())?;
+        Ok(packet("PUSH", key, body.as_bytes(), rigauth::now()))
+    });
+}
+
+/// Send datagram built by `build` every `interval` seconds until shutdown is requested.
+/// `to` can be a broadcast address.
+pub fn send_every<F>(to: &str, interval: u64, mut build: F)
+where
+    F: FnMut() -> Result<Vec<u8>, String>,
+{
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(s) => s,
+        Err(e) => {
+            println!("ERROR: Can not open UDP socket: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = socket.set_broadcast(true) {
+        println!("ERROR: Can not enable UDP broadcast: {}", e);
+    }
+
+    let interval = Duration::from_secs(interval.max(1));
+    let mut next = Instant::now();
+    while !sys::shutdown_requested() {
+        if Instant::now() < next {
+            thread::sleep(Duration::from_millis(500));
+            continue;
+        }
+        next += interval;
+
+        match build() {
+            Ok(packet) => if let Err(e) = socket.send_to(&packet, to) {
+                println!("ERROR: Can not send to {}: {}", to, e);
+            },
+            Err(e) => println!("ERROR: {}", e),
+        }
+    }
+}
+
+/// Datagram of `kind` signed with key if set
+pub fn packet(kind: &str, key: Option<&str>, body: &[u8], now: u64) -> Vec<u8> {
+    let (ts, signature) = match key {
+        Some(key) => rigauth::sign_datagram(key, kind, body, now),
+        None => (now.to_string(), String::from("-")),
+    };
+    let mut packet = format!("{} {}\n", ts, signature).into_bytes();
+    packet.extend_from_slice(body);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_packet() {
+        let body = b"hostname = \"rig1\"\n";
+        assert_eq!(
+            packet("PUSH", None, body, 1_540_000_000),
+            b"1540000000 -\nhostname = \"rig1\"\n".to_vec()
+        );
+
+        let signed = packet("PUSH", Some("farm-key"), body, 1_540_000_000);
+        let signature = rigauth::sign("farm-key", &[b"PUSH", b"1540000000", body]);
+        assert_eq!(signed, format!("1540000000 {}\nhostname = \"rig1\"\n", signature).into_bytes());
+    }
+}
@@ -0,0 +1,156 @@
+//! Background checks.
+//! Requests are answered from the latest sample, so slow `nvidia-smi` or
+//! `journalctl` calls never block them.
+
+use check::{CheckResult, Checker};
+use history::History;
+
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Snapshot {
+    result: CheckResult,
+    at: Instant,
+}
+
+impl Snapshot {
+    fn new(result: CheckResult) -> Snapshot {
+        Snapshot {
+            result,
+            at: Instant::now(),
+        }
+    }
+}
+
+pub struct Sampler {
+    pub checker: Checker,
+    latest: RwLock<Snapshot>,
+    history: RwLock<History>,
+}
+
+impl Sampler {
+    /// Take the first sample and keep refreshing it every `interval` seconds in background
+    pub fn start(checker: Checker) -> Arc<Sampler> {
+        let sampler = Arc::new(Sampler::new(checker));
+
+        let s = sampler.clone();
+        thread::spawn(move || {
+            let interval = Duration::from_secs(s.checker.cfg.interval.max(1));
+            let save_interval = Duration::from_secs(s.checker.cfg.history.save_interval);
+            let mut saved = Instant::now();
+            loop {
+                thread::sleep(interval);
+                s.refresh();
+
+                if save_interval.as_secs() > 0 && saved.elapsed() >= save_interval {
+                    s.save_history();
+                    saved = Instant::now();
+                }
+            }
+        });
+        sampler
+    }
+
+    fn new(checker: Checker) -> Sampler {
+        let mut history = History::new(checker.cfg.history.size);
+        if let Some(ref path) = checker.cfg.history.file {
+            if Path::new(path).exists() {
+                if let Err(e) = history.load(path) {
+                    println!("ERROR: {}", e);
+                }
+            }
+        }
+
+        let first = checker.check_all();
+        history.push(first.clone());
+        Sampler {
+            checker,
+            latest: RwLock::new(Snapshot::new(first)),
+            history: RwLock::new(history),
+        }
+    }
+
+    /// Replace latest sample with a new check and add it to history
+    fn refresh(&self) {
+        let r = self.checker.check_all();
+        self.history.write().unwrap().push(r.clone());
+        *self.latest.write().unwrap() = Snapshot::new(r);
+    }
+
+    /// Latest check result with its age
+    pub fn latest(&self) -> CheckResult {
+        let snapshot = self.latest.read().unwrap();
+        let mut r = snapshot.result.clone();
+        r.age = snapshot.at.elapsed().as_secs();
+        r
+    }
+
+    /// Check results taken after unix time `since`
+    pub fn history(&self, since: u64) -> Vec<CheckResult> {
+        self.history.read().unwrap().since(since)
+    }
+
+    /// Save history to configured file if any
+    pub fn save_history(&self) {
+        if let Some(ref path) = self.checker.cfg.history.file {
+            if let Err(e) = self.history.read().unwrap().save(path) {
+                println!("ERROR: {}", e);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use sys::fake::{FakeRoot, FakeRunner};
+
+    /// Rig with single RX 580 card
+    fn amd_rig(temp: &str) -> FakeRoot {
+        let root = FakeRoot::new();
+        let dev = "/sys/devices/pci0000:00/0000:01:00.0";
+        root.write(&format!("{}/uevent", dev), "")
+            .symlink("/sys/class/hwmon/hwmon0/device", dev)
+            .write("/sys/class/hwmon/hwmon0/name", "amdgpu\n")
+            .write("/sys/class/hwmon/hwmon0/temp1_input", temp);
+        root
+    }
+
+    fn sampler(root: &FakeRoot) -> Sampler {
+        let runner = FakeRunner::default().with("hostname", "rig01\n");
+        Sampler::new(Checker::new(Config::default(), root.host(runner)))
+    }
+
+    #[test]
+    fn latest_sample_age() {
+        let root = amd_rig("62000\n");
+        let sampler = sampler(&root);
+        assert_eq!(sampler.latest().age, 0);
+
+        sampler.latest.write().unwrap().at = Instant::now() - Duration::from_secs(25);
+        let r = sampler.latest();
+        assert_eq!(r.age, 25);
+        assert_eq!(r.hostname, "rig01");
+    }
+
+    #[test]
+    fn refresh_replaces_snapshot_and_keeps_history() {
+        let root = amd_rig("62000\n");
+        let sampler = sampler(&root);
+        sampler.latest.write().unwrap().at = Instant::now() - Duration::from_secs(25);
+        assert_eq!(sampler.latest().temp, vec![62]);
+
+        root.write("/sys/class/hwmon/hwmon0/temp1_input", "71000\n");
+        sampler.refresh();
+        let r = sampler.latest();
+        assert_eq!(r.temp, vec![71]);
+        assert_eq!(r.age, 0);
+
+        let temps: Vec<Vec<i32>> = sampler.history(0).into_iter().map(|r| r.temp).collect();
+        assert_eq!(temps, vec![vec![62], vec![71]]);
+    }
+}
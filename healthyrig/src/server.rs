@@ -0,0 +1,634 @@
+use serde::Serialize;
+use serde_json;
+use tiny_http::{Header, Request, Response, Server, SslConfig};
+use toml;
+
+use actions::Action;
+use rigauth;
+use check::{CheckResult, ServiceStatus};
+use config::TlsCfg;
+use metrics;
+use finding::{Code, Finding};
+use gpu::Gpu;
+use miner::MinerStats;
+
+use sampler::Sampler;
+use sys;
+
+use std::fs;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// POST endpoints are action names under this path
+static ACTIONS_PREFIX: &str = "/actions/";
+
+/// Known GET endpoints
+static ROUTES: &[&str] = &[
+    "/", "/health", "/temps", "/service", "/logs", "/miner", "/history", "/metrics",
+    "/version",
+];
+
+/// Larger request bodies are refused, endpoints take none
+const MAX_BODY: u64 = 64 * 1024;
+
+/// Findings reported at /miner
+static MINER_CODES: &[Code] = &[Code::MinerApi, Code::LowHashrate, Code::RejectRatio];
+
+/// Output format of check results.
+/// TOML is the default because ThorinPi expects it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.trim().to_lowercase().as_str() {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// Pick format from `?format=` query parameter first and `Accept` header after
+    fn negotiate(request: &Request) -> Format {
+        let accept = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Accept"))
+            .map(|h| h.value.as_str());
+        Format::choose(request.url(), accept)
+    }
+
+    fn choose(url: &str, accept: Option<&str>) -> Format {
+        if let Some(f) = query_param(url, "format").and_then(Format::parse) {
+            return f;
+        }
+
+        match accept {
+            Some(a) if a.contains("application/json") => Format::Json,
+            _ => Format::Toml,
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match *self {
+            Format::Toml => "application/toml; charset=utf-8",
+            Format::Json => "application/json; charset=utf-8",
+        }
+    }
+
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<String, String> {
+        match *self {
+            // Going through Value puts plain values before tables whatever fields order is
+            Format::Toml => toml::Value::try_from(value)
+                .and_then(|v| toml::to_string(&v))
+                .map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+
+/// Response parts, kept apart until response is signed
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    headers: Vec<Header>,
+}
+
+impl Reply {
+    fn new(content_type: &'static str, body: Vec<u8>) -> Reply {
+        Reply {
+            status: 200,
+            content_type,
+            body,
+            headers: Vec::new(),
+        }
+    }
+
+    fn status(mut self, code: u16) -> Reply {
+        self.status = code;
+        self
+    }
+
+    fn header(mut self, field: &str, value: &str) -> Reply {
+        self.headers.push(header(field, value));
+        self
+    }
+}
+
+
+#[derive(Debug, Serialize)]
+struct HealthResult {
+    hostname: String,
+    age: u64,
+    service: bool,
+    hw_errors: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct TempsResult {
+    hostname: String,
+    age: u64,
+    temp: Vec<i32>,
+    gpus: Vec<Gpu>,
+}
+
+#[derive(Debug, Serialize)]
+struct ServiceResult {
+    hostname: String,
+    age: u64,
+    name: String,
+    service: bool,
+    services: Vec<ServiceStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct LogsResult {
+    hostname: String,
+    age: u64,
+    log_errors: bool,
+    findings: Vec<Finding>,
+}
+
+#[derive(Debug, Serialize)]
+struct MinerResult {
+    hostname: String,
+    age: u64,
+    findings: Vec<Finding>,
+    miner: Option<MinerStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryResult {
+    hostname: String,
+    samples: Vec<CheckResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct ActionResult {
+    hostname: String,
+    action: Action,
+    /// Restarted or stopped service
+    service: Option<String>,
+    ok: bool,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct VersionResult {
+    name: &'static str,
+    version: &'static str,
+}
+
+
+pub fn run(sampler: Arc<Sampler>) {
+    let cfg = &sampler.checker.cfg;
+    let addr = format!("{}:{}", cfg.bind, cfg.port.unwrap_or(4242));
+    let server = match cfg.tls {
+        Some(ref tls) => match ssl_config(tls) {
+            Ok(ssl) => Server::https(addr.as_str(), ssl),
+            Err(e) => {
+                println!("ERROR: Can not read TLS certificate or key: {}", e);
+                return;
+            }
+        },
+        None => Server::http(addr.as_str()),
+    };
+    let server = match server {
+        Ok(s) => s,
+        Err(e) => {
+            println!("ERROR: Can not start server at {}: {}", addr, e);
+            return;
+        }
+    };
+    let scheme = if cfg.tls.is_some() { "https" } else { "http" };
+    println!("Server started at {}://{}", scheme, addr);
+    let mut nonces = rigauth::Nonces::default();
+    sys::catch_shutdown();
+    while !sys::shutdown_requested() {
+        let mut request = match server.recv_timeout(Duration::from_millis(500)) {
+            Ok(Some(r)) => r,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("ERROR {:?}", e);
+                continue;
+            }
+        };
+        let verified = check_method(&request).and_then(|_| match cfg.key {
+            Some(ref key) => read_body(&mut request)
+                .and_then(|body| verify(key, &request, &body, &mut nonces))
+                .map(Some),
+            None => Ok(None),
+        });
+        let reply = verified
+            .as_ref()
+            .map_err(|&code| code)
+            .and_then(|_| route(&request, &sampler))
+            .unwrap_or_else(|code| error(code, &request));
+        println!(
+            "{}REQUEST {} {} {} -> {}",
+            if reply.status >= 400 { "ERROR " } else { "" },
+            remote_addr(&request),
+            request.method(),
+            request.url(),
+            reply.status
+        );
+
+        let mut response = Response::from_data(reply.body.clone())
+            .with_status_code(reply.status)
+            .with_header(header("Content-Type", reply.content_type));
+        for h in reply.headers {
+            response.add_header(h);
+        }
+        if let (Some(key), Ok(Some(request_signature))) = (cfg.key.as_ref(), verified) {
+            let (ts, signature) =
+                rigauth::sign_response(key, &request_signature, &reply.body, rigauth::now());
+            response.add_header(header(rigauth::TIMESTAMP_HEADER, &ts));
+            response.add_header(header(rigauth::SIGNATURE_HEADER, &signature));
+        }
+        if let Err(e) = request.respond(response) {
+            println!("ERROR {:?}", e);
+        }
+    }
+
+    println!("Shutting down");
+    sampler.save_history();
+}
+
+
+fn ssl_config(tls: &TlsCfg) -> io::Result<SslConfig> {
+    Ok(SslConfig {
+        certificate: fs::read(&tls.cert)?,
+        private_key: fs::read(&tls.key)?,
+    })
+}
+
+
+/// Client address for logs
+fn remote_addr(request: &Request) -> String {
+    request
+        .remote_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_else(|| String::from("-"))
+}
+
+
+/// Request body for signature check
+fn read_body(request: &mut Request) -> Result<Vec<u8>, u16> {
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| {
+            println!("ERROR: Can not read request body: {}", e);
+            400u16
+        })?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(413);
+    }
+    Ok(body)
+}
+
+
+/// Check request signature and nonce, get signature to sign response with
+fn verify(
+    key: &str,
+    request: &Request,
+    body: &[u8],
+    nonces: &mut rigauth::Nonces,
+) -> Result<String, u16> {
+    let signed = rigauth::Request {
+        method: request.method().as_str(),
+        url: request.url(),
+        timestamp: header_value(request, rigauth::TIMESTAMP_HEADER),
+        nonce: header_value(request, rigauth::NONCE_HEADER),
+        signature: header_value(request, rigauth::SIGNATURE_HEADER),
+        body,
+    };
+    rigauth::verify_request(key, &signed, nonces, rigauth::now()).map_err(|e| {
+        println!("ERROR: Refused request from {}: {}", remote_addr(request), e);
+        401
+    })
+}
+
+
+/// Refuse unknown paths and wrong methods before checking signature
+fn check_method(request: &Request) -> Result<(), u16> {
+    let path = request.url().split('?').next().unwrap_or("");
+    if request.method().as_str() != allowed_method(path).ok_or(404u16)? {
+        return Err(405);
+    }
+    Ok(())
+}
+
+
+/// Build response for request or return HTTP error status code
+fn route(request: &Request, sampler: &Sampler) -> Result<Reply, u16> {
+    let path = request.url().split('?').next().unwrap_or("");
+    let format = Format::negotiate(request);
+    if let Some(action) = path.strip_prefix(ACTIONS_PREFIX).and_then(Action::parse) {
+        return run_action(request, sampler, action, format);
+    }
+    let r = sampler.latest();
+    match path {
+        "/" => reply(format, &r),
+        "/health" => reply(
+            format,
+            &HealthResult {
+                hostname: r.hostname,
+                age: r.age,
+                service: r.service,
+                hw_errors: r.hw_errors,
+            },
+        ),
+        "/temps" => reply(
+            format,
+            &TempsResult {
+                hostname: r.hostname,
+                age: r.age,
+                temp: r.temp,
+                gpus: r.gpus,
+            },
+        ),
+        "/service" => reply(
+            format,
+            &ServiceResult {
+                hostname: r.hostname,
+                age: r.age,
+                name: sampler.checker.cfg.service.clone(),
+                service: r.service,
+                services: r.services,
+            },
+        ),
+        "/logs" => {
+            let findings: Vec<Finding> = r
+                .findings
+                .into_iter()
+                .filter(|f| f.code == Code::LogPattern)
+                .collect();
+            reply(
+                format,
+                &LogsResult {
+                    hostname: r.hostname,
+                    age: r.age,
+                    log_errors: findings.iter().any(|f| f.is_critical()),
+                    findings,
+                },
+            )
+        }
+        "/miner" => reply(
+            format,
+            &MinerResult {
+                hostname: r.hostname,
+                age: r.age,
+                findings: r
+                    .findings
+                    .into_iter()
+                    .filter(|f| MINER_CODES.contains(&f.code))
+                    .collect(),
+                miner: r.miner,
+            },
+        ),
+        "/history" => {
+            let since = match query_param(request.url(), "since") {
+                Some(v) => v.parse::<u64>().map_err(|_| 400u16)?,
+                None => 0,
+            };
+            reply(
+                format,
+                &HistoryResult {
+                    hostname: r.hostname,
+                    samples: sampler.history(since),
+                },
+            )
+        }
+        "/metrics" => Ok(Reply::new(
+            metrics::CONTENT_TYPE,
+            metrics::render_check(&sampler.checker.cfg, &r).into_bytes(),
+        )),
+        "/version" => reply(
+            format,
+            &VersionResult {
+                name: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+            },
+        ),
+        _ => Err(404),
+    }
+}
+
+
+/// HTTP method path answers to, None for unknown path
+fn allowed_method(path: &str) -> Option<&'static str> {
+    if ROUTES.contains(&path) {
+        return Some("GET");
+    }
+    path.strip_prefix(ACTIONS_PREFIX)
+        .and_then(Action::parse)
+        .map(|_| "POST")
+}
+
+
+/// Run authorized action, every attempt goes to audit log
+fn run_action(
+    request: &Request,
+    sampler: &Sampler,
+    action: Action,
+    format: Format,
+) -> Result<Reply, u16> {
+    let cfg = &sampler.checker.cfg.actions;
+    if let Err(code) = cfg.authorize(action, header_value(request, "Authorization")) {
+        cfg.audit(&format!("{} {} denied {}", remote_addr(request), action.name(), code));
+        let reply = error(code, request);
+        return Ok(match code {
+            401 => reply.header("WWW-Authenticate", "Bearer"),
+            _ => reply,
+        });
+    }
+
+    let service = match action {
+        Action::Restart | Action::Stop => {
+            let name = query_param(request.url(), "service");
+            match sampler.checker.cfg.action_unit(name) {
+                Some(unit) => Some(unit),
+                None => {
+                    cfg.audit(&format!(
+                        "{} {} {} denied 400",
+                        remote_addr(request),
+                        action.name(),
+                        name.unwrap_or("-")
+                    ));
+                    return Err(400);
+                }
+            }
+        }
+        Action::Reboot | Action::Poweroff => None,
+    };
+
+    let res = sampler
+        .checker
+        .run_action(action, service.as_deref().unwrap_or(""));
+    cfg.audit(&format!(
+        "{} {} {} {}",
+        remote_addr(request),
+        action.name(),
+        service.as_deref().unwrap_or("-"),
+        res.as_ref().err().map(|e| e.as_str()).unwrap_or("ok")
+    ));
+    let code = if res.is_ok() { 200 } else { 500 };
+    reply(
+        format,
+        &ActionResult {
+            hostname: sampler.latest().hostname,
+            action,
+            service,
+            ok: res.is_ok(),
+            message: res.err().unwrap_or_default(),
+        },
+    ).map(|r| r.status(code))
+}
+
+
+fn header_value<'a>(request: &'a Request, field: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(field))
+        .map(|h| h.value.as_str())
+}
+
+/// Value of URL query parameter
+fn query_param<'a>(url: &'a str, name: &str) -> Option<&'a str> {
+    let query = url.split_once('?').map(|(_, q)| q).unwrap_or("");
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|&(k, _)| k == name)
+        .map(|(_, v)| v)
+}
+
+fn reply<T: Serialize>(format: Format, value: &T) -> Result<Reply, u16> {
+    match format.serialize(value) {
+        Ok(body) => Ok(Reply::new(format.content_type(), body.into_bytes())),
+        Err(e) => {
+            println!("ERROR serialize {:?}", e);
+            Err(500)
+        }
+    }
+}
+
+fn error(code: u16, request: &Request) -> Reply {
+    let message = match code {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    };
+    let reply = Reply::new(
+        "text/plain; charset=utf-8",
+        format!("{} {}", code, message).into_bytes(),
+    ).status(code);
+    match code {
+        405 => {
+            let path = request.url().split('?').next().unwrap_or("");
+            reply.header("Allow", allowed_method(path).unwrap_or("GET"))
+        }
+        _ => reply,
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use check::CheckResult;
+    use config::ServiceKind;
+    use os::OsStats;
+    use serde_json::Value;
+
+    #[derive(Serialize)]
+    struct Sample {
+        hostname: &'static str,
+        temp: Vec<i32>,
+        service: bool,
+    }
+
+    #[test]
+    fn negotiate_format() {
+        assert_eq!(Format::choose("/", None), Format::Toml);
+        assert_eq!(Format::choose("/", Some("text/html")), Format::Toml);
+        assert_eq!(Format::choose("/", Some("text/html, application/json")), Format::Json);
+        assert_eq!(Format::choose("/?format=json", None), Format::Json);
+        // query parameter wins over Accept header, unknown values are ignored
+        assert_eq!(Format::choose("/?a=1&format=TOML", Some("application/json")), Format::Toml);
+        assert_eq!(Format::choose("/?format=xml", Some("application/json")), Format::Json);
+    }
+
+    #[test]
+    fn serialize_formats() {
+        let r = Sample {
+            hostname: "rig01",
+            temp: vec![61, 65],
+            service: true,
+        };
+        assert_eq!(
+            Format::Toml.serialize(&r).unwrap(),
+            "hostname = \"rig01\"\nservice = true\ntemp = [61, 65]\n"
+        );
+        let json: Value = serde_json::from_str(&Format::Json.serialize(&r).unwrap()).unwrap();
+        assert_eq!(json["hostname"], "rig01");
+        assert_eq!(json["temp"][1], 65);
+    }
+
+    #[test]
+    fn serialize_services_toml() {
+        let r = CheckResult {
+            hostname: String::from("rig01"),
+            timestamp: 1_543_658_400,
+            age: 0,
+            temp: Vec::new(),
+            service: true,
+            hw_errors: false,
+            services: vec![ServiceStatus {
+                name: String::from("miner"),
+                kind: ServiceKind::Systemd,
+                required: true,
+                active: true,
+            }],
+            gpus: Vec::new(),
+            findings: Vec::new(),
+            miner: None,
+            os: OsStats::default(),
+        };
+        let body = Format::Toml.serialize(&r).unwrap();
+        let services = body.find("[[services]]").unwrap();
+        assert!(body.find("findings = []").unwrap() < services);
+        assert!(body.find("gpus = []").unwrap() < services);
+    }
+
+    #[test]
+    fn route_methods() {
+        for path in ROUTES {
+            assert_eq!(allowed_method(path), Some("GET"));
+        }
+        assert_eq!(allowed_method("/actions/restart"), Some("POST"));
+        assert_eq!(allowed_method("/actions/poweroff"), Some("POST"));
+        assert_eq!(allowed_method("/actions/format"), None);
+        assert_eq!(allowed_method("/nope"), None);
+        assert_eq!(allowed_method("/metrics/"), None);
+        assert_eq!(allowed_method(""), None);
+    }
+}
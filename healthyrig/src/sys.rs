@@ -0,0 +1,230 @@
+//! Access to host filesystem and commands.
+//! Checks never touch `/sys` or call programs directly, so they can run against
+//! fake filesystem tree and recorded command outputs.
+
+use std::ffi::CString;
+use std::fs::{read_link, File};
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_shutdown_signal(_: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Catch SIGTERM and SIGINT instead of dying, see `shutdown_requested`
+pub fn catch_shutdown() {
+    let handler = on_shutdown_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+    }
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Finished command result
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs external programs
+pub trait Runner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output>;
+}
+
+/// Runs real programs
+pub struct SystemRunner;
+
+impl Runner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        Command::new(program).args(args).output().map(|out| Output {
+            success: out.status.success(),
+            stdout: String::from(String::from_utf8_lossy(&out.stdout)),
+            stderr: String::from(String::from_utf8_lossy(&out.stderr)),
+        })
+    }
+}
+
+pub struct Host {
+    root: PathBuf,
+    runner: Box<dyn Runner + Send + Sync>,
+}
+
+impl Host {
+    pub fn new<P: Into<PathBuf>>(root: P, runner: Box<dyn Runner + Send + Sync>) -> Host {
+        Host {
+            root: root.into(),
+            runner,
+        }
+    }
+
+    /// Real host with filesystem mounted at root
+    pub fn system<P: Into<PathBuf>>(root: P) -> Host {
+        Host::new(root, Box::new(SystemRunner))
+    }
+
+    /// Resolve absolute host path under filesystem root
+    pub fn path<P: AsRef<Path>>(&self, p: P) -> PathBuf {
+        let p = p.as_ref();
+        self.root.join(p.strip_prefix("/").unwrap_or(p))
+    }
+
+    pub fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        self.runner.run(program, args)
+    }
+
+    pub fn open<P: AsRef<Path>>(&self, p: P) -> io::Result<File> {
+        File::open(self.path(p))
+    }
+
+    pub fn read_string<P: AsRef<Path>>(&self, p: P) -> Option<String> {
+        let mut s = String::new();
+        File::open(self.path(p))
+            .and_then(|mut f| f.read_to_string(&mut s))
+            .ok()
+            .map(|_| s)
+    }
+
+    pub fn read_number<T: FromStr, P: AsRef<Path>>(&self, p: P) -> Option<T> {
+        self.read_string(p)
+            .and_then(|s| s.trim().parse::<T>().ok())
+    }
+
+    /// Filesystem size and space available to unprivileged users in bytes
+    pub fn disk_space<P: AsRef<Path>>(&self, p: P) -> io::Result<(u64, u64)> {
+        let path = CString::new(self.path(p).into_os_string().into_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut st: libc::statvfs = unsafe { mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let block = st.f_frsize as u64;
+        Ok((st.f_blocks as u64 * block, st.f_bavail as u64 * block))
+    }
+
+    /// Last component of symlink target
+    pub fn link_name<P: AsRef<Path>>(&self, p: P) -> Option<String> {
+        read_link(self.path(p))
+            .ok()
+            .and_then(|d| d.file_name().map(|n| n.to_string_lossy().into_owned()))
+    }
+}
+
+/// Fake host for tests: temporary filesystem root and recorded command outputs
+#[cfg(test)]
+pub mod fake {
+    use super::{Host, Output, Runner};
+
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_ROOT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Answers with recorded stdout keyed by full command line.
+    /// Unknown commands fail as if program was not installed.
+    #[derive(Default)]
+    pub struct FakeRunner {
+        outputs: HashMap<String, Output>,
+    }
+
+    impl FakeRunner {
+        pub fn with(mut self, cmdline: &str, stdout: &str) -> FakeRunner {
+            self.outputs.insert(
+                String::from(cmdline),
+                Output {
+                    success: true,
+                    stdout: String::from(stdout),
+                    stderr: String::new(),
+                },
+            );
+            self
+        }
+
+        /// Command that exits with error after printing stdout
+        pub fn failing(mut self, cmdline: &str, stdout: &str) -> FakeRunner {
+            self = self.with(cmdline, stdout);
+            if let Some(out) = self.outputs.get_mut(cmdline) {
+                out.success = false;
+            }
+            self
+        }
+    }
+
+    impl Runner for FakeRunner {
+        fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+            let mut cmdline = vec![program];
+            cmdline.extend_from_slice(args);
+            self.outputs
+                .get(&cmdline.join(" "))
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, cmdline.join(" ")))
+        }
+    }
+
+    /// Temporary filesystem root removed on drop
+    pub struct FakeRoot {
+        pub path: PathBuf,
+    }
+
+    impl FakeRoot {
+        pub fn new() -> FakeRoot {
+            let path = env::temp_dir().join(format!(
+                "healthyrig-test-{}-{}",
+                process::id(),
+                NEXT_ROOT.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::create_dir_all(&path).unwrap();
+            FakeRoot { path }
+        }
+
+        /// Write file at absolute host path
+        pub fn write(&self, p: &str, content: &str) -> &FakeRoot {
+            let p = self.host_path(p);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            fs::write(p, content).unwrap();
+            self
+        }
+
+        /// Symlink absolute host path to another one
+        pub fn symlink(&self, p: &str, target: &str) -> &FakeRoot {
+            let p = self.host_path(p);
+            fs::create_dir_all(p.parent().unwrap()).unwrap();
+            symlink(self.host_path(target), p).unwrap();
+            self
+        }
+
+        pub fn host(&self, runner: FakeRunner) -> Host {
+            Host::new(&self.path, Box::new(runner))
+        }
+
+        fn host_path(&self, p: &str) -> PathBuf {
+            self.path.join(Path::new(p).strip_prefix("/").unwrap())
+        }
+    }
+
+    impl Drop for FakeRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Shared key authentication between healthyrig and ThorinPi.
+//! Signature is hex HMAC-SHA256 of newline joined parts:
+//! request is signed as `METHOD`, `URL` (path and query), timestamp, nonce
+//! and hex SHA-256 of body,
+//! response as request signature, timestamp and body,
+//! UDP heartbeat and discovery beacon as `PUSH` or `BEACON`, timestamp and body.
+
+extern crate hmac;
+extern crate sha2;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub static TIMESTAMP_HEADER: &str = "X-Healthyrig-Timestamp";
+pub static NONCE_HEADER: &str = "X-Healthyrig-Nonce";
+pub static SIGNATURE_HEADER: &str = "X-Healthyrig-Signature";
+
+/// Messages signed earlier or later than this many seconds are refused
+pub const MAX_CLOCK_SKEW: u64 = 120;
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn mac(key: &str, parts: &[&[u8]]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            mac.update(b"\n");
+        }
+        mac.update(part);
+    }
+    mac
+}
+
+pub fn sign(key: &str, parts: &[&[u8]]) -> String {
+    to_hex(&mac(key, parts).finalize().into_bytes())
+}
+
+/// Constant time signature check
+pub fn verify(key: &str, parts: &[&[u8]], signature: &str) -> bool {
+    match from_hex(signature) {
+        Some(bytes) => mac(key, parts).verify_slice(&bytes).is_ok(),
+        None => false,
+    }
+}
+
+/// Random hex string to make every request signature unique
+pub fn nonce() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(to_hex(&bytes))
+}
+
+/// Request as seen by healthyrig, header values are None when missing
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub url: &'a str,
+    pub timestamp: Option<&'a str>,
+    pub nonce: Option<&'a str>,
+    pub signature: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+/// Nonces of recently accepted requests, so a captured request can not be replayed
+#[derive(Debug, Default)]
+pub struct Nonces {
+    seen: HashMap<String, u64>,
+}
+
+impl Nonces {
+    /// Remember nonce, error if it was already used within clock skew window
+    fn insert(&mut self, nonce: &str, ts: u64, now: u64) -> Result<(), String> {
+        self.seen.retain(|_, &mut seen| seen + MAX_CLOCK_SKEW >= now);
+        if self.seen.contains_key(nonce) {
+            return Err(format!("nonce {} is reused", nonce));
+        }
+        self.seen.insert(String::from(nonce), ts.max(now));
+        Ok(())
+    }
+}
+
+/// Timestamp and signature headers values for request with nonce
+pub fn sign_request(
+    key: &str,
+    method: &str,
+    url: &str,
+    nonce: &str,
+    body: &[u8],
+    now: u64,
+) -> (String, String) {
+    let ts = now.to_string();
+    let hash = body_hash(body);
+    let signature = sign(
+        key,
+        &[method.as_bytes(), url.as_bytes(), ts.as_bytes(), nonce.as_bytes(), hash.as_bytes()],
+    );
+    (ts, signature)
+}
+
+/// Check request signature and that its nonce is not reused,
+/// get signature back to sign response with
+pub fn verify_request(
+    key: &str,
+    request: &Request,
+    nonces: &mut Nonces,
+    now: u64,
+) -> Result<String, String> {
+    let (timestamp, nonce, signature) = match (request.timestamp, request.nonce, request.signature)
+    {
+        (Some(t), Some(n), Some(s)) => (t.trim(), n.trim(), s.trim()),
+        _ => return Err(String::from("request is not signed")),
+    };
+    let ts = check_timestamp(timestamp, now)?;
+    let hash = body_hash(request.body);
+    let parts: &[&[u8]] = &[
+        request.method.as_bytes(),
+        request.url.as_bytes(),
+        timestamp.as_bytes(),
+        nonce.as_bytes(),
+        hash.as_bytes(),
+    ];
+    if !verify(key, parts, signature) {
+        return Err(String::from("bad signature"));
+    }
+    nonces.insert(nonce, ts, now)?;
+    Ok(String::from(signature))
+}
+
+/// Timestamp and signature headers values for response body
+pub fn sign_response(
+    key: &str,
+    request_signature: &str,
+    body: &[u8],
+    now: u64,
+) -> (String, String) {
+    let ts = now.to_string();
+    let signature = sign(key, &[request_signature.as_bytes(), ts.as_bytes(), body]);
+    (ts, signature)
+}
+
+/// Check response was signed with the key for our request not long ago
+pub fn verify_response(
+    key: &str,
+    request_signature: &str,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    body: &[u8],
+    now: u64,
+) -> Result<(), String> {
+    let (timestamp, signature) = match (timestamp, signature) {
+        (Some(t), Some(s)) => (t.trim(), s.trim()),
+        _ => return Err(String::from("response is not signed")),
+    };
+    check_timestamp(timestamp, now)?;
+    if !verify(key, &[request_signature.as_bytes(), timestamp.as_bytes(), body], signature) {
+        return Err(String::from("bad response signature"));
+    }
+    Ok(())
+}
+
+/// Timestamp and signature for UDP datagram body of `kind` (`PUSH` or `BEACON`)
+pub fn sign_datagram(key: &str, kind: &str, body: &[u8], now: u64) -> (String, String) {
+    let ts = now.to_string();
+    let signature = sign(key, &[kind.as_bytes(), ts.as_bytes(), body]);
+    (ts, signature)
+}
+
+/// Check UDP datagram of `kind` was signed with the key not long ago
+pub fn verify_datagram(
+    key: &str,
+    kind: &str,
+    timestamp: &str,
+    signature: &str,
+    body: &[u8],
+    now: u64,
+) -> Result<(), String> {
+    check_timestamp(timestamp, now)?;
+    if !verify(key, &[kind.as_bytes(), timestamp.as_bytes(), body], signature) {
+        return Err(format!("bad {} signature", kind));
+    }
+    Ok(())
+}
+
+fn check_timestamp(timestamp: &str, now: u64) -> Result<u64, String> {
+    let ts = timestamp
+        .parse::<u64>()
+        .map_err(|_| format!("bad timestamp {}", timestamp))?;
+    if ts.max(now) - ts.min(now) > MAX_CLOCK_SKEW {
+        return Err(format!("timestamp {} is {}s off", ts, now as i64 - ts as i64));
+    }
+    Ok(ts)
+}
+
+fn body_hash(body: &[u8]) -> String {
+    to_hex(&Sha256::digest(body))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static KEY: &str = "farm-key";
+    const NOW: u64 = 1_540_000_000;
+
+    #[test]
+    fn sign_known_value() {
+        // python3 -c 'import hmac; print(hmac.new(b"key", b"GET\n/\n1", "sha256").hexdigest())'
+        assert_eq!(
+            sign("key", &[b"GET", b"/", b"1"]),
+            "9ccf06f083f0a8d91038fddfac736c0644c4c41da6e5cf09b6efb1b9603f8d7d"
+        );
+    }
+
+    fn request<'a>(url: &'a str, ts: &'a str, nonce: &'a str, sig: &'a str) -> Request<'a> {
+        Request {
+            method: "POST",
+            url,
+            timestamp: Some(ts),
+            nonce: Some(nonce),
+            signature: Some(sig),
+            body: b"",
+        }
+    }
+
+    #[test]
+    fn verify_signed_request() {
+        let (ts, sig) = sign_request(KEY, "POST", "/actions/restart", "n1", b"", NOW + 10);
+        assert_eq!(ts, "1540000010");
+        let verify_at = |req: &Request| verify_request(KEY, req, &mut Nonces::default(), NOW);
+
+        let req = request("/actions/restart", "1540000010", "n1", &sig);
+        assert_eq!(verify_at(&req), Ok(sig.clone()));
+        assert!(verify_at(&request("/actions/poweroff", "1540000010", "n1", &sig)).is_err());
+        assert!(verify_at(&request("/actions/restart", "1540000011", "n1", &sig)).is_err());
+        assert!(verify_at(&request("/actions/restart", "1540000010", "n2", &sig)).is_err());
+        assert!(verify_at(&request("/actions/restart", "1540000010", "n1", &sig[2..])).is_err());
+        assert!(verify_at(&request("/actions/restart", "1540000010", "n1", &sig[1..])).is_err());
+        assert!(verify_at(&Request { body: b"x", ..req }).is_err());
+        assert!(verify_at(&Request { nonce: None, ..req }).is_err());
+        assert!(verify_at(&Request { timestamp: None, signature: None, ..req }).is_err());
+
+        let (_, old) = sign_request(KEY, "POST", "/actions/restart", "n1", b"", NOW - 1000);
+        assert!(verify_at(&request("/actions/restart", "1539999000", "n1", &old)).is_err());
+    }
+
+    #[test]
+    fn refuse_replayed_request() {
+        let mut nonces = Nonces::default();
+        let (ts, sig) = sign_request(KEY, "POST", "/actions/poweroff", "n1", b"", NOW);
+        let req = request("/actions/poweroff", &ts, "n1", &sig);
+
+        assert!(verify_request(KEY, &req, &mut nonces, NOW).is_ok());
+        assert!(verify_request(KEY, &req, &mut nonces, NOW + 1).is_err());
+        assert!(verify_request(KEY, &req, &mut nonces, NOW + MAX_CLOCK_SKEW).is_err());
+        // forged nonce is not remembered
+        let forged = request("/actions/poweroff", &ts, "n2", &sig);
+        assert!(verify_request(KEY, &forged, &mut nonces, NOW).is_err());
+        assert_eq!(nonces.seen.len(), 1);
+
+        let (ts, sig) = sign_request(KEY, "POST", "/actions/poweroff", "n2", b"", NOW);
+        let req = request("/actions/poweroff", &ts, "n2", &sig);
+        assert!(verify_request(KEY, &req, &mut nonces, NOW + 1).is_ok());
+        assert!(nonces.seen.contains_key("n2"));
+        // old nonces are forgotten once their requests are too old to be accepted
+        let (ts, sig) = sign_request(KEY, "GET", "/", "n3", b"", NOW + 200);
+        let req = Request { method: "GET", ..request("/", &ts, "n3", &sig) };
+        assert!(verify_request(KEY, &req, &mut nonces, NOW + 200).is_ok());
+        assert_eq!(nonces.seen.len(), 1);
+    }
+
+    #[test]
+    fn random_nonce() {
+        let nonce = nonce().unwrap();
+        assert_eq!(nonce.len(), 32);
+        assert_ne!(nonce, super::nonce().unwrap());
+    }
+
+    #[test]
+    fn signed_response() {
+        let body = b"hw_errors = false\n";
+        let (ts, sig) = sign_response(KEY, "abcd", body, NOW);
+        assert_eq!(ts, "1540000000");
+        let verify_at = |request_sig, body: &[u8], now| {
+            verify_response(KEY, request_sig, Some(&ts), Some(&sig), body, now)
+        };
+
+        assert_eq!(verify_at("abcd", body, NOW + 5), Ok(()));
+        assert!(verify_at("abcd", b"hw_errors = true\n", NOW).is_err());
+        assert!(verify_at("abce", body, NOW).is_err());
+        assert!(verify_at("abcd", body, NOW + 1000).is_err());
+        assert!(verify_response(KEY, "abcd", None, Some(&sig), body, NOW).is_err());
+    }
+
+    #[test]
+    fn signed_datagram() {
+        let body = b"hostname = \"rig1\"\n";
+        let (ts, sig) = sign_datagram(KEY, "PUSH", body, NOW);
+        assert_eq!(verify_datagram(KEY, "PUSH", &ts, &sig, body, NOW), Ok(()));
+        assert!(verify_datagram(KEY, "BEACON", &ts, &sig, body, NOW).is_err());
+        assert!(verify_datagram("other", "PUSH", &ts, &sig, body, NOW).is_err());
+        assert!(verify_datagram(KEY, "PUSH", &ts, &sig, body, NOW + 1000).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Check result types shared between healthyrig and ThorinPi.
+//! healthyrig serializes them, ThorinPi reads them back,
+//! so codes added to newer healthyrig are read as `Code::Unknown`.
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+use std::fmt;
+
+/// How bad is the problem.
+/// Only critical findings are reported as `hw_errors`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    #[default]
+    Critical,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Code {
+    /// Detected GPUs count differs from expected
+    GpuCount,
+    /// GPU temperature is above threshold
+    GpuTemp,
+    /// Can not read GPU temperature
+    TempRead,
+    /// GPU fan is too slow
+    GpuFan,
+    /// Miner log rule matched
+    LogPattern,
+    /// Kernel log rule matched
+    KernelLog,
+    /// nvidia-smi exited with error, some NVIDIA GPUs may be missing
+    NvidiaSmi,
+    /// Can not get stats from miner API
+    MinerApi,
+    /// Rig or GPU hashrate is below expected
+    LowHashrate,
+    /// Too many rejected shares
+    RejectRatio,
+    /// CPU temperature is above threshold
+    CpuTemp,
+    /// Available memory is below threshold
+    LowMemory,
+    /// Root filesystem free space is below threshold
+    DiskSpace,
+    /// Code from newer healthyrig version, never reported by healthyrig itself
+    #[serde(other)]
+    Unknown,
+}
+
+impl Code {
+    /// Codes healthyrig reports
+    pub fn all() -> &'static [Code] {
+        &[
+            Code::GpuCount,
+            Code::GpuTemp,
+            Code::TempRead,
+            Code::GpuFan,
+            Code::LogPattern,
+            Code::KernelLog,
+            Code::NvidiaSmi,
+            Code::MinerApi,
+            Code::LowHashrate,
+            Code::RejectRatio,
+            Code::CpuTemp,
+            Code::LowMemory,
+            Code::DiskSpace,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Code::GpuCount => "gpu_count",
+            Code::GpuTemp => "gpu_temp",
+            Code::TempRead => "temp_read",
+            Code::GpuFan => "gpu_fan",
+            Code::LogPattern => "log_pattern",
+            Code::KernelLog => "kernel_log",
+            Code::NvidiaSmi => "nvidia_smi",
+            Code::MinerApi => "miner_api",
+            Code::LowHashrate => "low_hashrate",
+            Code::RejectRatio => "reject_ratio",
+            Code::CpuTemp => "cpu_temp",
+            Code::LowMemory => "low_memory",
+            Code::DiskSpace => "disk_space",
+            Code::Unknown => "unknown",
+        }
+    }
+}
+
+/// Single detected problem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub code: Code,
+    pub severity: Severity,
+    #[serde(default)]
+    pub message: String,
+    /// Log rule name for log findings
+    pub rule: Option<String>,
+    /// Log line that triggered finding
+    pub line: Option<String>,
+    pub gpu: Option<usize>,
+}
+
+impl Finding {
+    pub fn new(code: Code, severity: Severity, message: String) -> Finding {
+        Finding {
+            code,
+            severity,
+            message,
+            rule: None,
+            line: None,
+            gpu: None,
+        }
+    }
+
+    pub fn gpu(mut self, gpu: usize) -> Finding {
+        self.gpu = Some(gpu);
+        self
+    }
+
+    pub fn is_critical(&self) -> bool {
+        self.severity == Severity::Critical
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}/{:?}", self.code, self.severity)?;
+        if let Some(gpu) = self.gpu {
+            write!(f, " GPU{}", gpu)?;
+        }
+        write!(f, " {}", self.message)?;
+        if let Some(ref rule) = self.rule {
+            write!(f, " [{}]", rule)?;
+        }
+        if let Some(ref line) = self.line {
+            write!(f, " >> {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+    extern crate toml;
+
+    use super::*;
+    use self::serde_json::Value;
+
+    #[test]
+    fn serialize_finding() {
+        let f = Finding::new(Code::GpuTemp, Severity::Warning, String::from("GPU 1 is hot")).gpu(1);
+        let json: Value = serde_json::to_value(&f).unwrap();
+        assert_eq!(json["code"], "gpu_temp");
+        assert_eq!(json["severity"], "warning");
+        assert_eq!(json["gpu"], 1);
+        assert_eq!(json["rule"], Value::Null);
+
+        // unset fields are left out of TOML
+        assert_eq!(
+            toml::to_string(&f).unwrap(),
+            "code = \"gpu_temp\"\nseverity = \"warning\"\nmessage = \"GPU 1 is hot\"\ngpu = 1\n"
+        );
+    }
+
+    #[test]
+    fn names_match_serialized() {
+        for code in Code::all() {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.name());
+        }
+        for severity in &[Severity::Info, Severity::Warning, Severity::Critical] {
+            assert_eq!(serde_json::to_value(severity).unwrap(), severity.name());
+        }
+        assert!(Severity::Critical > Severity::Warning && Severity::Warning > Severity::Info);
+    }
+
+    #[test]
+    fn read_newer_finding() {
+        let f: Finding = toml::from_str(
+            "code = \"fan_on_fire\"\n\
+             severity = \"warning\"\n\
+             gpu = 2\n",
+        ).unwrap();
+        assert_eq!(f.code, Code::Unknown);
+        assert_eq!(f.severity, Severity::Warning);
+        assert_eq!(f.message, "");
+        assert_eq!(f.to_string(), "Unknown/Warning GPU2 ");
+    }
+}
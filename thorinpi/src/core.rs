@@ -16,10 +16,27 @@ pub struct VentCfg {
 
 #[derive(Debug, Deserialize)]
 pub struct RigCfg {
+    /// healthyrig address, can be left out for rig found by `id`
+    #[serde(default)]
     pub uri: String,
+    /// Rig ID or hostname announced by healthyrig discovery beacon
+    pub id: Option<String>,
     pub gpio_power: u8,
     pub gpio_switch: u8,
     pub critical_gpu_temp: Option<u32>,
+    /// healthyrig actions token, OS is shut down with power button only if not set
+    pub token: Option<String>,
+    /// healthyrig shared key, requests are signed and responses verified when set
+    pub key: Option<String>,
+    /// PEM file with CA that issued healthyrig certificate, for https uri
+    pub ca: Option<String>,
+    /// healthyrig certificate SHA-256 fingerprint, for self-signed https certificate
+    pub fingerprint: Option<String>,
+    /// Rig pushes heartbeats to `listen` address instead of being polled
+    #[serde(default)]
+    pub push: bool,
+    /// Seconds without heartbeat treated as failed check (default 30)
+    pub heartbeat_timeout: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,4 +44,8 @@ pub struct Settings {
     pub sensors: Vec<TempSensorCfg>,
     pub vents: Vec<VentCfg>,
     pub rigs: Vec<RigCfg>,
+    /// UDP address to receive healthyrig heartbeats at, e.g. "0.0.0.0:4243"
+    pub listen: Option<String>,
+    /// UDP address to receive healthyrig discovery beacons at, e.g. "0.0.0.0:4244"
+    pub discover: Option<String>,
 }
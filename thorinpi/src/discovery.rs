@@ -0,0 +1,210 @@
+//! healthyrig LAN discovery beacons.
+//! Beacon datagram has the same `TIMESTAMP SIGNATURE\n` header as push heartbeat,
+//! body tells rig ID, hostname and HTTP port, address is taken from datagram source.
+
+use rigauth;
+use toml;
+
+use core::RigCfg;
+
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Deserialize)]
+pub struct Beacon {
+    pub id: String,
+    pub hostname: String,
+    /// healthyrig HTTP port, not set when it only pushes heartbeats
+    pub port: Option<u16>,
+    pub scheme: String,
+}
+
+impl Beacon {
+    /// Malformed beacon is refused so it can not match or redirect a rig
+    pub fn parse(body: &str) -> Result<Beacon, String> {
+        let beacon = toml::from_str::<Beacon>(body).map_err(|e| format!("bad beacon: {}", e))?;
+        if beacon.id.trim().is_empty() || beacon.hostname.trim().is_empty() {
+            return Err(String::from("bad beacon: empty id or hostname"));
+        }
+        if beacon.scheme != "http" && beacon.scheme != "https" {
+            return Err(format!("bad beacon: unknown scheme {}", beacon.scheme));
+        }
+        if beacon.port == Some(0) {
+            return Err(String::from("bad beacon: port 0"));
+        }
+        Ok(beacon)
+    }
+}
+
+/// How a rig with `id` follows its beacons
+#[derive(Debug)]
+pub struct Follow {
+    id: String,
+    key: Option<String>,
+    /// Rig has action token or TLS settings, unsigned beacon could lead them to another host
+    signed_only: bool,
+    /// Scheme of configured uri, beacon can not change it
+    scheme: Option<String>,
+    /// Timestamp of last accepted signed beacon
+    last: u64,
+}
+
+impl Follow {
+    /// None for rig without `id`
+    pub fn new(cfg: &RigCfg) -> Option<Follow> {
+        let id = match cfg.id {
+            Some(ref id) if !id.is_empty() => id.clone(),
+            _ => return None,
+        };
+        let tls = cfg.ca.is_some() || cfg.fingerprint.is_some();
+        let scheme = match cfg.uri.find("://") {
+            Some(i) => Some(String::from(&cfg.uri[..i])),
+            None if tls => Some(String::from("https")),
+            None => None,
+        };
+        let signed_only = cfg.token.is_some() || tls;
+        if signed_only && cfg.key.is_none() {
+            warn!("Rig {} has token or TLS settings but no key, its beacons are ignored", id);
+        }
+        Some(Follow {
+            id,
+            key: cfg.key.clone(),
+            signed_only,
+            scheme,
+            last: 0,
+        })
+    }
+
+    /// Beacon announces this rig
+    pub fn is(&self, beacon: &Beacon) -> bool {
+        self.id == beacon.id || self.id == beacon.hostname
+    }
+
+    /// Address rig was found at and its uri when beacon tells HTTP port.
+    /// Signed beacon must be newer than the last one, so a replayed one can not move the rig.
+    pub fn locate(
+        &mut self,
+        beacon: &Beacon,
+        from: &SocketAddr,
+        packet: (&str, &str, &str),
+        now: u64,
+    ) -> Result<(IpAddr, Option<String>), String> {
+        let (timestamp, signature, body) = packet;
+        match self.key {
+            Some(ref key) => {
+                let body = body.as_bytes();
+                rigauth::verify_datagram(key, "BEACON", timestamp, signature, body, now)?;
+                let ts = timestamp
+                    .parse::<u64>()
+                    .map_err(|_| format!("bad timestamp {}", timestamp))?;
+                if ts <= self.last {
+                    return Err(format!("timestamp {} is not after last beacon {}", ts, self.last));
+                }
+                self.last = ts;
+            }
+            None if self.signed_only => {
+                return Err(String::from("unsigned beacon for rig with token or TLS settings"))
+            }
+            None => {}
+        }
+        let scheme = self.scheme.as_deref().unwrap_or(&beacon.scheme);
+        let uri = beacon
+            .port
+            .map(|port| format!("{}://{}", scheme, SocketAddr::new(from.ip(), port)));
+        Ok((from.ip(), uri))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_beacon() {
+        let b = Beacon::parse(
+            "id = \"rig1\"\nhostname = \"rig01\"\nport = 4242\nscheme = \"https\"\n",
+        ).unwrap();
+        assert_eq!(b.id, "rig1");
+        assert_eq!(b.port, Some(4242));
+        assert!(Beacon::parse("id = \"rig1\"\nhostname = \"rig01\"\nscheme = \"http\"\n").is_ok());
+
+        for bad in &[
+            "",
+            "id = \"\"\nhostname = \"rig01\"\nscheme = \"http\"\n",
+            "id = \"rig1\"\nhostname = \" \"\nscheme = \"http\"\n",
+            "id = \"rig1\"\nhostname = \"rig01\"\nscheme = \"ftp\"\n",
+            "id = \"rig1\"\nhostname = \"rig01\"\nport = 0\nscheme = \"http\"\n",
+        ] {
+            assert!(Beacon::parse(bad).is_err(), "{}", bad);
+        }
+    }
+    static KEY: &str = "farm-key";
+    const NOW: u64 = 1_540_000_000;
+    static BEACON: &str = "id = \"rig1\"\nhostname = \"rig01\"\nport = 4242\nscheme = \"http\"\n";
+
+    fn rig(extra: &str) -> RigCfg {
+        toml::from_str(&format!("gpio_power=4\ngpio_switch=17\n{}", extra)).unwrap()
+    }
+
+    fn follow(extra: &str) -> Follow {
+        Follow::new(&rig(&format!("id=\"rig1\"\n{}", extra))).unwrap()
+    }
+
+    fn locate(follow: &mut Follow, key: Option<&str>, ts: u64) -> Result<Option<String>, String> {
+        let (ts, signature) = match key {
+            Some(key) => rigauth::sign_datagram(key, "BEACON", BEACON.as_bytes(), ts),
+            None => (ts.to_string(), String::from("-")),
+        };
+        let from = "192.168.10.50:50000".parse().unwrap();
+        let beacon = Beacon::parse(BEACON).unwrap();
+        follow
+            .locate(&beacon, &from, (&ts, &signature, BEACON), NOW)
+            .map(|(ip, uri)| {
+                assert_eq!(ip.to_string(), "192.168.10.50");
+                uri
+            })
+    }
+
+    #[test]
+    fn follow_unsigned_beacon() {
+        let mut f = follow("");
+        assert!(f.is(&Beacon::parse(BEACON).unwrap()));
+        assert_eq!(locate(&mut f, None, NOW), Ok(Some(String::from("http://192.168.10.50:4242"))));
+        assert!(Follow::new(&rig("uri=\"http://10.0.0.5:4242\"")).is_none());
+    }
+
+    #[test]
+    fn unsigned_beacon_ignored_for_rig_with_secrets() {
+        for extra in &[
+            "token=\"change-me\"",
+            "ca=\"/etc/thorinpi/ca.pem\"",
+            "fingerprint=\"bbab\"",
+        ] {
+            assert!(locate(&mut follow(extra), None, NOW).is_err(), "{}", extra);
+        }
+        let mut f = follow("token=\"change-me\"\nkey=\"farm-key\"");
+        assert!(locate(&mut f, None, NOW).is_err());
+        assert!(locate(&mut f, Some("other"), NOW).is_err());
+        let http = Ok(Some(String::from("http://192.168.10.50:4242")));
+        assert_eq!(locate(&mut f, Some(KEY), NOW), http);
+    }
+
+    #[test]
+    fn keep_configured_scheme() {
+        // beacon says http
+        let https = Ok(Some(String::from("https://192.168.10.50:4242")));
+        let mut f = follow("uri=\"https://10.0.0.5:4242\"");
+        assert_eq!(locate(&mut f, None, NOW), https);
+
+        let mut f = follow("key=\"farm-key\"\nfingerprint=\"bbab\"");
+        assert_eq!(locate(&mut f, Some(KEY), NOW), https);
+    }
+
+    #[test]
+    fn refuse_replayed_beacon() {
+        let mut f = follow("key=\"farm-key\"");
+        assert!(locate(&mut f, Some(KEY), NOW - 10).is_ok());
+        assert!(locate(&mut f, Some(KEY), NOW - 10).is_err());
+        assert!(locate(&mut f, Some(KEY), NOW - 20).is_err());
+        assert!(locate(&mut f, Some(KEY), NOW).is_ok());
+    }
+}
@@ -4,17 +4,26 @@ extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate reqwest;
+extern crate rigauth;
+extern crate rigproto;
 extern crate rppal;
+extern crate rustls;
+extern crate rustls_pemfile;
 #[macro_use]
 extern crate serde_derive;
+extern crate sha2;
 extern crate toml;
 
 mod core;
+mod discovery;
 mod rig;
 mod vent;
+mod push;
 mod sensor;
+mod tls;
 
 use core::Settings;
+use discovery::Beacon;
 use rig::{Rig, RigCheckResult};
 use sensor::TSensor;
 use vent::Vent;
@@ -105,6 +114,10 @@ fn application(settings: Settings) {
     let mut vents = Vec::<Vent>::new();
 
     for rig in &settings.rigs {
+        if rig.uri.is_empty() && !rig.id.iter().any(|id| !id.is_empty()) {
+            error!("Rig without uri or id skipped, it can not be found");
+            continue;
+        }
         rigs.push(Rig::new(rig));
     }
 
@@ -119,11 +132,42 @@ fn application(settings: Settings) {
     }
     debug!("Sensors after vents {:?}", sensors);
 
+    let heartbeats = settings.listen.as_ref().map(|addr| {
+        push::listen(addr).unwrap_or_else(|e| panic!("{}", e))
+    });
+    let beacons = settings.discover.as_ref().map(|addr| {
+        push::listen(addr).unwrap_or_else(|e| panic!("{}", e))
+    });
+
     let mut cycle = 0;
 
     loop {
         // let ref mut ss:Vec<Rc<TSensor>> = sensors;
         let mut gpu_temps = Vec::<isize>::new();
+        if let Some(ref rx) = beacons {
+            while let Ok(b) = rx.try_recv() {
+                let found = push::parse(&b.packet).and_then(|packet| {
+                    let beacon = Beacon::parse(packet.2)?;
+                    match rigs.iter_mut().find(|r| r.is(&beacon)) {
+                        Some(r) => r.discovered(&beacon, &b.from, packet),
+                        None => Err(format!("unknown rig {}", beacon.id)),
+                    }
+                });
+                if let Err(e) = found {
+                    debug!("Beacon from {} ignored: {}", b.from, e);
+                }
+            }
+        }
+        if let Some(ref rx) = heartbeats {
+            while let Ok(hb) = rx.try_recv() {
+                match rigs.iter_mut().find(|r| r.accepts(&hb.from)) {
+                    Some(r) => if let Err(e) = r.heartbeat(&hb.packet) {
+                        warn!("{:?} heartbeat from {} refused: {}", r, hb.from, e);
+                    },
+                    None => debug!("Heartbeat from unknown rig {}", hb.from),
+                }
+            }
+        }
         for r in &mut rigs {
             if let Some(mut res) = r.handle() {
                 gpu_temps.append(&mut res.temp.clone());
@@ -164,11 +208,13 @@ fn format_temperature(sensors: &Vec<Rc<RefCell<TSensor>>>) -> String {
 
 fn show_rig_check(check: &RigCheckResult) {
     info!(
-        "{} led_on:{} service:{} errors:{} temps:{:?}",
+        "{} led_on:{} service:{} errors:{} findings:{} reboots:{} temps:{:?}",
         check.hostname,
         check.led_on.unwrap_or(false),
         check.service,
         check.hw_errors,
+        check.findings.len(),
+        check.unplanned_reboots,
         check.temp.clone() // check
                            //     .temp
                            //     .clone()
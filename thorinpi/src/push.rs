@@ -0,0 +1,173 @@
+//! Listener for healthyrig push heartbeats and discovery beacons.
+//! Datagram is `TIMESTAMP SIGNATURE\n` line followed by TOML body.
+
+use rigauth;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::str;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// Biggest datagram accepted
+const MAX_PACKET: usize = 65_507;
+
+#[derive(Debug)]
+pub struct Datagram {
+    pub from: SocketAddr,
+    pub packet: Vec<u8>,
+}
+
+/// Receive datagrams in background thread
+pub fn listen(addr: &str) -> Result<Receiver<Datagram>, String> {
+    let socket = UdpSocket::bind(addr).map_err(|e| format!("Can not listen at {}: {}", addr, e))?;
+    info!("Listening UDP at {}", addr);
+
+    let (tx, rx) = channel();
+    let addr = String::from(addr);
+    thread::spawn(move || {
+        let mut buf = vec![0u8; MAX_PACKET];
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    let heartbeat = Datagram {
+                        from,
+                        packet: buf[..len].to_vec(),
+                    };
+                    if tx.send(heartbeat).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warn!("UDP receive at {} failed: {}", addr, e),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Split datagram into timestamp, signature and body
+pub fn parse(packet: &[u8]) -> Result<(&str, &str, &str), String> {
+    let text = str::from_utf8(packet).map_err(|_| String::from("datagram is not UTF-8"))?;
+    let (head, body) = match text.find('\n') {
+        Some(i) => (&text[..i], &text[i + 1..]),
+        None => return Err(String::from("datagram has no body")),
+    };
+    let mut head = head.split_whitespace();
+    match (head.next(), head.next()) {
+        (Some(ts), Some(signature)) => Ok((ts, signature, body)),
+        _ => Err(String::from("bad datagram header")),
+    }
+}
+
+/// Last accepted heartbeat of a rig.
+/// Heartbeats are aged by their own timestamp and must come in order,
+/// so a replayed or delayed one can not keep a dead rig alive.
+#[derive(Debug, Default)]
+pub struct LastHeartbeat {
+    /// Timestamp and body
+    last: Option<(u64, String)>,
+}
+
+impl LastHeartbeat {
+    /// Keep heartbeat newer than the last one, signature is verified when key is set
+    pub fn accept(&mut self, key: Option<&str>, packet: &[u8], now: u64) -> Result<(), String> {
+        let (timestamp, signature, body) = parse(packet)?;
+        if let Some(key) = key {
+            rigauth::verify_datagram(key, "PUSH", timestamp, signature, body.as_bytes(), now)?;
+        }
+        let ts = timestamp
+            .parse::<u64>()
+            .map_err(|_| format!("bad timestamp {}", timestamp))?;
+        if ts > now + rigauth::MAX_CLOCK_SKEW {
+            return Err(format!("timestamp {} is {}s ahead", ts, ts - now));
+        }
+        if let Some((last, _)) = self.last {
+            if ts <= last {
+                return Err(format!("timestamp {} is not after last heartbeat {}", ts, last));
+            }
+        }
+        self.last = Some((ts, String::from(body)));
+        Ok(())
+    }
+
+    /// Body of last heartbeat unless it is older than `timeout` seconds
+    pub fn body(&self, timeout: u64, now: u64) -> Result<String, String> {
+        match self.last {
+            Some((ts, ref body)) if now.saturating_sub(ts) <= timeout => Ok(body.clone()),
+            Some((ts, _)) => Err(format!("no heartbeat for {}s", now.saturating_sub(ts))),
+            None => Err(String::from("no heartbeat received")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static KEY: &str = "farm-key";
+    const NOW: u64 = 1_540_000_000;
+
+    fn packet(key: Option<&str>, ts: u64, body: &str) -> Vec<u8> {
+        let (ts, signature) = match key {
+            Some(key) => rigauth::sign_datagram(key, "PUSH", body.as_bytes(), ts),
+            None => (ts.to_string(), String::from("-")),
+        };
+        format!("{} {}\n{}", ts, signature, body).into_bytes()
+    }
+
+    #[test]
+    fn parse_datagram() {
+        assert_eq!(
+            parse(b"1540000000 -\nhostname = \"rig1\"\n"),
+            Ok(("1540000000", "-", "hostname = \"rig1\"\n"))
+        );
+        assert_eq!(parse(b"1540000000  abcd \n"), Ok(("1540000000", "abcd", "")));
+        assert!(parse(b"1540000000 -").is_err());
+        assert!(parse(b"1540000000\nhostname = \"rig1\"\n").is_err());
+        assert!(parse(b"1540000000 -\n\xff").is_err());
+    }
+
+    #[test]
+    fn last_heartbeat() {
+        let mut hb = LastHeartbeat::default();
+        assert_eq!(hb.body(30, NOW), Err(String::from("no heartbeat received")));
+
+        hb.accept(Some(KEY), &packet(Some(KEY), NOW - 5, "a = 1\n"), NOW).unwrap();
+        assert_eq!(hb.body(30, NOW), Ok(String::from("a = 1\n")));
+        // aged by its own timestamp, not by arrival
+        assert_eq!(hb.body(30, NOW + 25), Ok(String::from("a = 1\n")));
+        assert_eq!(hb.body(30, NOW + 26), Err(String::from("no heartbeat for 31s")));
+
+        hb.accept(Some(KEY), &packet(Some(KEY), NOW, "a = 2\n"), NOW).unwrap();
+        assert_eq!(hb.body(30, NOW), Ok(String::from("a = 2\n")));
+    }
+
+    #[test]
+    fn refuse_replayed_heartbeat() {
+        let mut hb = LastHeartbeat::default();
+        let first = packet(Some(KEY), NOW - 10, "a = 1\n");
+        hb.accept(Some(KEY), &first, NOW - 10).unwrap();
+        hb.accept(Some(KEY), &packet(Some(KEY), NOW, "a = 2\n"), NOW).unwrap();
+
+        // replayed and delayed heartbeats are refused and do not refresh the rig
+        assert!(hb.accept(Some(KEY), &first, NOW + 20).is_err());
+        assert!(hb.accept(Some(KEY), &packet(Some(KEY), NOW, "a = 3\n"), NOW + 20).is_err());
+        assert_eq!(hb.body(30, NOW + 31), Err(String::from("no heartbeat for 31s")));
+
+        // forged or unsigned heartbeat is refused when key is set
+        assert!(hb.accept(Some(KEY), &packet(Some("other"), NOW + 1, "a = 4\n"), NOW).is_err());
+        assert!(hb.accept(Some(KEY), &packet(None, NOW + 1, "a = 4\n"), NOW).is_err());
+        assert_eq!(hb.body(30, NOW), Ok(String::from("a = 2\n")));
+    }
+
+    #[test]
+    fn unsigned_heartbeat() {
+        let mut hb = LastHeartbeat::default();
+        hb.accept(None, &packet(None, NOW, "a = 1\n"), NOW).unwrap();
+        assert!(hb.accept(None, &packet(None, NOW, "a = 2\n"), NOW).is_err());
+        // timestamp far ahead would keep the rig alive
+        assert!(hb.accept(None, &packet(None, NOW + 1000, "a = 3\n"), NOW).is_err());
+        assert!(hb.accept(None, b"soon -\na = 4\n", NOW).is_err());
+        assert_eq!(hb.body(30, NOW), Ok(String::from("a = 1\n")));
+    }
+}
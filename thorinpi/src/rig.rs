@@ -1,13 +1,21 @@
 use reqwest;
+use reqwest::blocking::{Client, RequestBuilder};
 use toml;
 
 use gpio_sensors::gpio::GpioPin;
 use gpio_sensors::gpio::gpio_pin_new;
 
+use rigauth;
+use discovery::{Beacon, Follow};
+use push::LastHeartbeat;
 use core::RigCfg;
+use rigproto::{Code, Finding, Severity};
+use tls;
 
 use std::error::Error;
 use std::fmt;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant};
 use std::thread;
 
@@ -21,6 +29,31 @@ const POWER_OFF: u64 = 180;
 const POWER_OFF_HARD_MAX: u64 = 240;
 /// Wait until error resolved
 const ERR_RESOLVE_WAIT: u64 = 30;
+/// Allowed wait for power off after OS shutdown requested from healthyrig
+const SHUTDOWN_WAIT: u64 = 90;
+/// healthyrig sample older than this means its checks are stuck
+const MAX_SAMPLE_AGE: u64 = 120;
+/// Default seconds without push heartbeat treated as failed check
+const HEARTBEAT_TIMEOUT: u64 = 30;
+
+/// GPU identity and readings reported by healthyrig
+#[derive(Debug, Clone, Deserialize)]
+pub struct GpuInfo {
+    pub index: usize,
+    pub vendor: String,
+    pub bus_id: Option<String>,
+    pub temp: Option<isize>,
+}
+
+impl fmt::Display for GpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GPU{} {}", self.index, self.vendor)?;
+        if let Some(ref bus_id) = self.bus_id {
+            write!(f, " {}", bus_id)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Deserialize)]
 pub struct RigCheckResult {
@@ -28,7 +61,69 @@ pub struct RigCheckResult {
     pub temp: Vec<isize>,
     pub service: bool,
     pub hw_errors: bool,
+    /// Seconds since healthyrig took the sample, 0 for old versions
+    #[serde(default)]
+    pub age: u64,
     pub led_on: Option<bool>,
+    #[serde(default)]
+    pub gpus: Vec<GpuInfo>,
+    #[serde(default)]
+    pub findings: Vec<Finding>,
+    /// Not reported by old healthyrig versions
+    #[serde(default)]
+    pub os: OsInfo,
+    /// Unplanned reboots ThorinPi noticed since it started, not sent by healthyrig
+    #[serde(skip)]
+    pub unplanned_reboots: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OsInfo {
+    /// Seconds since boot
+    pub uptime: Option<u64>,
+    /// Changes on every boot
+    pub boot_id: Option<String>,
+}
+
+impl RigCheckResult {
+    /// Human readable GPU name by index from findings
+    pub fn gpu_name(&self, index: usize) -> String {
+        self.gpus
+            .iter()
+            .find(|g| g.index == index)
+            .map(|g| g.to_string())
+            .unwrap_or(format!("GPU{}", index))
+    }
+}
+
+/// Notices boot ID changes ThorinPi did not cause
+#[derive(Debug, Default)]
+pub struct BootWatch {
+    /// Boot ID from the last check, forgotten when rig is powered off
+    boot_id: Option<String>,
+    /// Unplanned reboots noticed since ThorinPi start
+    pub reboots: u32,
+}
+
+impl BootWatch {
+    /// Remember boot ID from check, true if rig rebooted since the previous one
+    pub fn update(&mut self, boot_id: Option<&str>) -> bool {
+        let boot_id = match boot_id {
+            Some(b) => b,
+            None => return false,
+        };
+        let rebooted = self.boot_id.as_ref().map_or(false, |known| known != boot_id);
+        if rebooted {
+            self.reboots += 1;
+        }
+        self.boot_id = Some(String::from(boot_id));
+        rebooted
+    }
+
+    /// Rig is powered off by ThorinPi, its next boot is expected
+    pub fn forget(&mut self) {
+        self.boot_id = None;
+    }
 }
 
 #[derive(Debug)]
@@ -39,6 +134,8 @@ pub enum RigState {
     OnErr(Instant),
     /// Rig booting started at Instant
     Boot(Instant),
+    /// OS shutdown requested from healthyrig at Instant
+    Shutdown(Instant),
     /// Rig power off button clicked at Instant
     PowOff(Instant),
     /// Rig power off button pressed (not released) at Instant
@@ -51,6 +148,18 @@ pub enum RigState {
 pub struct Rig {
     hostname: String,
     uri: String,
+    /// Set for rig with `id`, found by beacons
+    follow: Option<Follow>,
+    token: Option<String>,
+    key: Option<String>,
+    /// Client build error is reported on every request instead of stopping ThorinPi
+    client: Result<Client, String>,
+    /// Rig addresses to match heartbeats by
+    addrs: Vec<IpAddr>,
+    push: bool,
+    heartbeat_timeout: u64,
+    heartbeat: LastHeartbeat,
+    boot: BootWatch,
     state: RigState,
     critical_temp: u32,
     pin_power: Box<GpioPin>,
@@ -69,9 +178,36 @@ impl Rig {
         psw.direction_output(0)
             .expect(format!("Can not set output mode for SWITCH pin {}", cfg.gpio_switch).as_str());
 
+        let client = http_client(cfg);
+        if let Err(ref e) = client {
+            error!("Rig {} can not be checked: {}", cfg.uri, e);
+        }
+
+        let addrs = if cfg.uri.is_empty() {
+            Vec::new()
+        } else {
+            reqwest::Url::parse(&cfg.uri)
+                .map_err(|e| e.to_string())
+                .and_then(|u| u.socket_addrs(|| None).map_err(|e| e.to_string()))
+                .map(|a| a.iter().map(|a| a.ip()).collect())
+                .unwrap_or_else(|e| {
+                    warn!("Can not resolve rig {} address: {}", cfg.uri, e);
+                    Vec::new()
+                })
+        };
+
         Rig {
-            hostname: cfg.uri.clone(), //String::from("N/A"),
+            hostname: cfg.id.clone().unwrap_or_else(|| cfg.uri.clone()), //String::from("N/A"),
             uri: cfg.uri.clone(),
+            follow: Follow::new(cfg),
+            token: cfg.token.clone(),
+            key: cfg.key.clone(),
+            client,
+            addrs,
+            push: cfg.push,
+            heartbeat_timeout: cfg.heartbeat_timeout.unwrap_or(HEARTBEAT_TIMEOUT),
+            heartbeat: LastHeartbeat::default(),
+            boot: BootWatch::default(),
             // Possible SHOULD BE OFF
             // state: RigState::On,
             state: RigState::Off(Instant::now() - Duration::from_secs(POWER_OFF)),
@@ -99,8 +235,8 @@ impl Rig {
         let now = Instant::now();
         match self.state {
             RigState::On => match self.request_check() {
-                Ok(check) => {
-                    self.process_checks(&check);
+                Ok(mut check) => {
+                    self.process_checks(&mut check);
                     return Some(check);
                 }
                 Err(err) => {
@@ -109,8 +245,8 @@ impl Rig {
                 }
             },
             RigState::OnErr(from) => match self.request_check() {
-                Ok(check) => {
-                    self.process_checks(&check);
+                Ok(mut check) => {
+                    self.process_checks(&mut check);
                     return Some(check);
                 }
                 Err(err) => {
@@ -122,9 +258,9 @@ impl Rig {
             },
             RigState::Boot(from) => if now - from > Duration::from_secs(BOOT_WAIT) {
                 match self.request_check() {
-                    Ok(check) => {
+                    Ok(mut check) => {
                         self.to_on();
-                        self.process_checks(&check);
+                        self.process_checks(&mut check);
                         return Some(check);
                     }
                     Err(err) => {
@@ -135,6 +271,10 @@ impl Rig {
             } else {
                 trace!("Wait {} at {} for boot", self.hostname, self.uri);
             },
+            RigState::Shutdown(from) => if shutdown_timed_out(from, now) {
+                warn!("{} OS shutdown timed out", self.hostname);
+                self.press_power_off();
+            },
             RigState::PowOff(from) => if now - from > Duration::from_secs(POWER_OFF_WAIT) {
                 self.to_power_off_hard();
             },
@@ -150,12 +290,63 @@ impl Rig {
         return None;
     }
 
-    fn process_checks(&mut self, res: &RigCheckResult) {
+    fn process_checks(&mut self, res: &mut RigCheckResult) {
+        if self.boot.update(res.os.boot_id.as_deref()) {
+            warn!(
+                "{} rebooted unexpectedly ({} times), up for {}s",
+                self.hostname,
+                self.boot.reboots,
+                res.os.uptime.unwrap_or(0)
+            );
+        }
+        res.unplanned_reboots = self.boot.reboots;
+
+        let mut rig_err = false;
+        for f in &res.findings {
+            match (f.code, f.severity) {
+                // Overheat or lost GPU - should turn off whatever severity is
+                (Code::GpuCount, _) => {
+                    warn!("{} {}", self.hostname, f);
+                    return self.to_power_off();
+                }
+                (Code::GpuTemp, _) => {
+                    warn!(
+                        "{} {} {}",
+                        self.hostname,
+                        f.gpu.map(|i| res.gpu_name(i)).unwrap_or_default(),
+                        f
+                    );
+                    return self.to_power_off();
+                }
+                (_, Severity::Critical) => {
+                    warn!("{} {}", self.hostname, f);
+                    return self.to_power_off();
+                }
+                // GPU sensor does not respond - might be a hang in progress
+                (Code::TempRead, _) => {
+                    warn!("{} {}", self.hostname, f);
+                    rig_err = true;
+                }
+                (_, Severity::Warning) => warn!("{} {}", self.hostname, f),
+                (_, Severity::Info) => debug!("{} {}", self.hostname, f),
+            }
+        }
+
         // Big erros - should turn off
+        // Old healthyrig versions report only this flag
         if res.hw_errors {
             warn!("{} HW errors reported", self.hostname);
             return self.to_power_off();
         }
+        for g in &res.gpus {
+            if let Some(t) = g.temp {
+                if t > self.critical_temp as isize {
+                    warn!("{} {} critical temperature {}C reported", self.hostname, g, t);
+                    return self.to_power_off();
+                }
+            }
+        }
+        // Old healthyrig versions report only temperatures
         for t in &res.temp {
             if t > &(self.critical_temp as isize) {
                 warn!("{} critical temperature {}C reported", self.hostname, t);
@@ -163,24 +354,59 @@ impl Rig {
             }
         }
         // Regular errors
+        if res.age > MAX_SAMPLE_AGE {
+            warn!("{} healthyrig sample is {}s old", self.hostname, res.age);
+            rig_err = true;
+        }
         if !res.service {
             warn!("{} mining service reported as DOWN", self.hostname);
+            rig_err = true;
+        }
+        if rig_err {
             self.to_on_err();
         }
     }
 
+    /// Heartbeat came from this rig address
+    pub fn accepts(&self, from: &SocketAddr) -> bool {
+        self.push && self.addrs.contains(&from.ip())
+    }
+
+    /// Beacon announces this rig
+    pub fn is(&self, beacon: &Beacon) -> bool {
+        self.follow.as_ref().map_or(false, |f| f.is(beacon))
+    }
+
+    /// Follow rig to address it was discovered at
+    pub fn discovered(
+        &mut self,
+        beacon: &Beacon,
+        from: &SocketAddr,
+        packet: (&str, &str, &str),
+    ) -> Result<(), String> {
+        let follow = self.follow.as_mut().ok_or_else(|| String::from("rig has no id"))?;
+        let (ip, uri) = follow.locate(beacon, from, packet, rigauth::now())?;
+        self.addrs = vec![ip];
+        if let Some(uri) = uri {
+            if uri != self.uri {
+                info!("{} found at {}", self.hostname, uri);
+                self.uri = uri;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep pushed check result, signature is verified when rig key is set
+    pub fn heartbeat(&mut self, packet: &[u8]) -> Result<(), String> {
+        self.heartbeat.accept(self.key.as_deref(), packet, rigauth::now())
+    }
+
     fn request_check(&mut self) -> Result<RigCheckResult, String> {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
-            .build()
-            .map_err(reqwest_err_map)?;
-
-        let result = client
-            .get(&self.uri)
-            .send()
-            .map_err(reqwest_err_map)?
-            .text()
-            .map_err(reqwest_err_map)?;
+        let result = if self.push {
+            self.last_heartbeat()?
+        } else {
+            self.send(self.client()?.get(&self.uri), "GET", &self.uri)?
+        };
 
         let resp =
             toml::from_str::<RigCheckResult>(&result).map_err(|e| String::from(e.description()));
@@ -195,13 +421,96 @@ impl Rig {
         });
     }
 
+    /// Missing heartbeat fails the same way as unanswered request
+    fn last_heartbeat(&self) -> Result<String, String> {
+        self.heartbeat.body(self.heartbeat_timeout, rigauth::now())
+    }
+
+    /// Ask healthyrig to run action
+    fn request_action(&self, action: &str) -> Result<(), String> {
+        let token = self.token.clone().ok_or_else(|| String::from("no token configured"))?;
+        let uri = format!("{}/actions/{}", self.uri.trim_end_matches('/'), action);
+        self.send(self.client()?.post(&uri).bearer_auth(token), "POST", &uri).map(|_| ())
+    }
+
+    fn client(&self) -> Result<&Client, String> {
+        self.client.as_ref().map_err(|e| e.clone())
+    }
+
+    /// Send request signed with rig key if set, verify response signature and get its body
+    fn send(
+        &self,
+        mut req: RequestBuilder,
+        method: &str,
+        uri: &str,
+    ) -> Result<String, String> {
+        if self.uri.is_empty() {
+            return Err(String::from("rig is not discovered yet"));
+        }
+        let mut signature = None;
+        if let Some(ref key) = self.key {
+            let url = reqwest::Url::parse(uri).map_err(|e| String::from(e.description()))?;
+            let path = match url.query() {
+                Some(q) => format!("{}?{}", url.path(), q),
+                None => String::from(url.path()),
+            };
+            let nonce = rigauth::nonce().map_err(|e| format!("Can not make nonce: {}", e))?;
+            // requests to healthyrig carry no body
+            let (ts, sig) = rigauth::sign_request(key, method, &path, &nonce, b"", rigauth::now());
+            req = req
+                .header(rigauth::TIMESTAMP_HEADER, ts)
+                .header(rigauth::NONCE_HEADER, nonce)
+                .header(rigauth::SIGNATURE_HEADER, sig.as_str());
+            signature = Some(sig);
+        }
+
+        let mut res = req.send().map_err(reqwest_err_map)?;
+        if !res.status().is_success() {
+            return Err(format!("healthyrig answered {}", res.status()));
+        }
+        let mut body = Vec::new();
+        res.read_to_end(&mut body).map_err(|e| String::from(e.description()))?;
+
+        if let (Some(key), Some(sig)) = (self.key.as_ref(), signature) {
+            let header = |name| {
+                res.headers().get(name).and_then(|v| v.to_str().ok())
+            };
+            rigauth::verify_response(
+                key,
+                &sig,
+                header(rigauth::TIMESTAMP_HEADER),
+                header(rigauth::SIGNATURE_HEADER),
+                &body,
+                rigauth::now(),
+            )?;
+        }
+        String::from_utf8(body).map_err(|e| String::from(e.description()))
+    }
+
     fn read_power_state(&mut self) -> bool {
         self.pin_power.read() > 0
     }
 
+    /// Shut OS down through healthyrig, power button is used if it fails
     fn to_power_off(&mut self) {
         match self.state {
-            RigState::On | RigState::OnErr(_) | RigState::Boot(_) => {
+            RigState::On | RigState::OnErr(_) | RigState::Boot(_) if self.token.is_some() => {
+                let res = self.request_action("poweroff");
+                match shutdown_state(&self.hostname, res, Instant::now()) {
+                    Some(state) => {
+                        self.state = state;
+                        self.boot.forget();
+                    }
+                    None => self.press_power_off(),
+                }
+            }
+            _ => self.press_power_off(),
+        }
+    }
+
+    fn press_power_off(&mut self) {
+        match self.state {
+            RigState::On | RigState::OnErr(_) | RigState::Boot(_) | RigState::Shutdown(_) => {
                 let offres = self.switch_pin_hight().and_then(|_| {
                     thread::sleep(Duration::from_millis(750));
                     self.switch_pin_low()
@@ -211,6 +520,7 @@ impl Rig {
                     Ok(_) => {
                         warn!("{} powering OFF", self.hostname);
                         self.state = RigState::PowOff(Instant::now());
+                        self.boot.forget();
                     }
                     Err(e) => error!(
                         "can not power switch pin for PowerOff for {}. {}",
@@ -291,6 +601,7 @@ impl Rig {
 
         info!("{} is OFF", self.hostname);
         self.state = RigState::Off(Instant::now());
+        self.boot.forget();
     }
 
     fn to_on(&mut self) {
@@ -360,7 +671,84 @@ impl fmt::Debug for Rig {
     }
 }
 
+/// State after poweroff action was requested from healthyrig,
+/// none when it failed and power button has to be pressed
+fn shutdown_state(hostname: &str, res: Result<(), String>, now: Instant) -> Option<RigState> {
+    match res {
+        Ok(_) => {
+            warn!("{} shutting down OS", hostname);
+            Some(RigState::Shutdown(now))
+        }
+        Err(e) => {
+            warn!("{} can not shut down OS. {}", hostname, e);
+            None
+        }
+    }
+}
+
+/// Power LED is still on too long after OS shutdown started at `from`
+fn shutdown_timed_out(from: Instant, now: Instant) -> bool {
+    now - from > Duration::from_secs(SHUTDOWN_WAIT)
+}
+
 fn reqwest_err_map(e: reqwest::Error) -> String {
-    return format!("REQWEST: {}", e.description());
+    return format!("REQWEST: {}", e);
     // return format!("{:?}", e);
 }
+
+/// HTTP client with rig TLS settings
+fn http_client(cfg: &RigCfg) -> Result<Client, String> {
+    let mut client = Client::builder().timeout(Duration::from_secs(10));
+    if let Some(tls) = tls::client_config(cfg)? {
+        client = client.use_preconfigured_tls(tls);
+    }
+    client
+        .build()
+        .map_err(|e| format!("Can not create HTTP client: {}", e))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_when_poweroff_accepted() {
+        let now = Instant::now();
+        match shutdown_state("rig1", Ok(()), now) {
+            Some(RigState::Shutdown(from)) => assert_eq!(from, now),
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    #[test]
+    fn press_button_when_poweroff_fails() {
+        let res = Err(String::from("healthyrig answered 403 Forbidden"));
+        assert!(shutdown_state("rig1", res, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn press_button_after_shutdown_wait() {
+        let from = Instant::now();
+        assert!(!shutdown_timed_out(from, from + Duration::from_secs(SHUTDOWN_WAIT)));
+        assert!(shutdown_timed_out(from, from + Duration::from_secs(SHUTDOWN_WAIT + 1)));
+    }
+    #[test]
+    fn notice_unplanned_reboot() {
+        let mut boot = BootWatch::default();
+        assert!(!boot.update(None));
+        assert!(!boot.update(Some("a1")));
+        assert!(!boot.update(Some("a1")));
+        // old healthyrig does not report boot ID
+        assert!(!boot.update(None));
+        assert!(boot.update(Some("b2")));
+        assert_eq!(boot.reboots, 1);
+
+        // reboot after power off by ThorinPi is planned
+        boot.forget();
+        assert!(!boot.update(Some("c3")));
+        assert_eq!(boot.reboots, 1);
+        assert!(boot.update(Some("d4")));
+        assert_eq!(boot.reboots, 2);
+    }
+}
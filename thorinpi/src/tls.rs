@@ -0,0 +1,136 @@
+//! TLS settings for healthyrig HTTPS requests.
+//! Rig certificate is trusted by pinned CA or by its SHA-256 fingerprint,
+//! the later is meant for self-signed certificates.
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+use rustls_pemfile;
+use sha2::{Digest, Sha256};
+
+use core::RigCfg;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Client config for rig, None means default verification against well known CAs
+pub fn client_config(cfg: &RigCfg) -> Result<Option<ClientConfig>, String> {
+    let builder = ClientConfig::builder().with_safe_defaults();
+    if let Some(ref fingerprint) = cfg.fingerprint {
+        let pin = parse_fingerprint(fingerprint)
+            .ok_or_else(|| format!("Bad certificate fingerprint {}", fingerprint))?;
+        return Ok(Some(
+            builder
+                .with_custom_certificate_verifier(Arc::new(PinnedCert(pin)))
+                .with_no_client_auth(),
+        ));
+    }
+    if let Some(ref ca) = cfg.ca {
+        let mut roots = RootCertStore::empty();
+        let file = File::open(ca).map_err(|e| format!("Can not open CA file {}: {}", ca, e))?;
+        let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+            .map_err(|e| format!("Can not read CA file {}: {}", ca, e))?;
+        for cert in certs {
+            roots
+                .add(&Certificate(cert))
+                .map_err(|e| format!("Bad CA certificate in {}: {}", ca, e))?;
+        }
+        if roots.is_empty() {
+            return Err(format!("No certificates found in CA file {}", ca));
+        }
+        return Ok(Some(builder.with_root_certificates(roots).with_no_client_auth()));
+    }
+    Ok(None)
+}
+
+/// Hex SHA-256, `:` separators and case are ignored
+fn parse_fingerprint(s: &str) -> Option<Vec<u8>> {
+    let hex: String = s.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Accepts only server certificate with known fingerprint, name and expiration are not checked
+struct PinnedCert(Vec<u8>);
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Sha256::digest(&end_entity.0).as_slice() == self.0.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(String::from("certificate fingerprint mismatch")))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    use std::env;
+    use std::fs;
+
+    static FINGERPRINT: &str = "bbabddab7a81049c1b654aa52f5cc227cfe485982e2c20bcb291dc496260915e";
+
+    fn rig(extra: &str) -> RigCfg {
+        let cfg = format!("uri=\"https://10.0.0.5:4242\"\ngpio_power=4\ngpio_switch=17\n{}", extra);
+        toml::from_str(&cfg).unwrap()
+    }
+
+    #[test]
+    fn read_fingerprint() {
+        let pin = parse_fingerprint(FINGERPRINT).unwrap();
+        assert_eq!(pin.len(), 32);
+        assert_eq!((pin[0], pin[31]), (0xbb, 0x5e));
+
+        let colons: Vec<String> = FINGERPRINT
+            .as_bytes()
+            .chunks(2)
+            .map(|b| String::from_utf8_lossy(b).to_uppercase())
+            .collect();
+        assert_eq!(parse_fingerprint(&colons.join(":")), Some(pin));
+
+        assert_eq!(parse_fingerprint(&FINGERPRINT[2..]), None);
+        assert_eq!(parse_fingerprint(&FINGERPRINT.replace('b', "g")), None);
+        assert_eq!(parse_fingerprint(&format!("+b{}", &FINGERPRINT[2..])), None);
+        // 64 bytes, but not 64 hex digits
+        let wide = format!("b\u{e9}{}", &FINGERPRINT[3..]);
+        assert_eq!(wide.len(), 64);
+        assert_eq!(parse_fingerprint(&wide), None);
+    }
+
+    #[test]
+    fn client_config_errors() {
+        assert!(client_config(&rig("")).unwrap().is_none());
+        let pinned = format!("fingerprint=\"{}\"", FINGERPRINT);
+        assert!(client_config(&rig(&pinned)).unwrap().is_some());
+
+        let err = client_config(&rig("fingerprint=\"bbab\"")).err().unwrap();
+        assert_eq!(err, "Bad certificate fingerprint bbab");
+
+        let err = client_config(&rig("ca=\"/nonexistent/ca.pem\"")).err().unwrap();
+        assert!(err.starts_with("Can not open CA file /nonexistent/ca.pem"), "{}", err);
+
+        let ca = env::temp_dir().join("thorinpi-test-empty-ca.pem");
+        fs::write(&ca, "not a certificate\n").unwrap();
+        let err = client_config(&rig(&format!("ca={:?}", ca.to_str().unwrap()))).err().unwrap();
+        fs::remove_file(&ca).unwrap();
+        assert!(err.starts_with("No certificates found in CA file"), "{}", err);
+    }
+}